@@ -36,6 +36,9 @@ export const config = {
       '03cb4d09e437d2a3497d6507fe62f66f668c9c647d4ea9ffb02c8845c5c53ce663'
   ],
   apiKey: env.API_KEY,
+  // Shared secret for verifying HMAC-signed requests from the canister
+  hmacSecret: env.CANISTER_HMAC_SECRET,
+  hmacMaxSkewSeconds: Number(env.CANISTER_HMAC_MAX_SKEW_SECONDS ?? 300),
   vaultDbPath: env.VAULT_DB_PATH ?? path.resolve(__dirname, '../data/vaults.json'),
   feeRecipientAddress:
     env.FEE_RECIPIENT_ADDRESS ??
@@ -47,9 +50,18 @@ export const config = {
   },
   fallbackBtcPriceUsd: Number(env.FALLBACK_BTC_PRICE_USD ?? 100_734.1),
   vaultMinConfirmations: Number(env.VAULT_MIN_CONFIRMATIONS ?? 6),
-  healthAtRiskRatioBps: Number(env.HEALTH_AT_RISK_RATIO_BPS ?? 15000)
+  healthAtRiskRatioBps: Number(env.HEALTH_AT_RISK_RATIO_BPS ?? 15000),
+  // at-risk vaults recover only at or above this ratio (mirrors the canister's health bands)
+  healthExitRatioBps: Number(env.HEALTH_EXIT_RATIO_BPS ?? 15500)
 };
 
+// Exact decimal string for whole sats, built without dividing as a float;
+// bitcoind accepts amounts as strings.
 export function satsToBtcString(sats: number): string {
-  return (sats / SATS_PER_BTC).toFixed(8);
+  if (!Number.isSafeInteger(sats) || sats < 0) {
+    throw new Error(`invalid sats amount: ${sats}`);
+  }
+  const whole = Math.floor(sats / SATS_PER_BTC);
+  const fraction = String(sats % SATS_PER_BTC).padStart(8, '0');
+  return `${whole}.${fraction}`;
 }
@@ -4,14 +4,27 @@ import { config } from './config.js';
 import mintRouter from './routes/mint.js';
 import vaultRouter from './routes/vaults.js';
 import withdrawRouter from './routes/withdraw.js';
+import { idempotentRequest } from './utils/idempotency.js';
+import { protocolVersionInfo, requireProtocolVersion } from './utils/protocolVersion.js';
+import { requireCanisterSignature, type RawBodyRequest } from './utils/requestSignature.js';
 
 const app = express();
 
-app.use(express.json({ limit: '1mb' }));
+app.use(
+  express.json({
+    limit: '1mb',
+    verify: (req, _res, buf) => {
+      (req as RawBodyRequest).rawBody = Buffer.from(buf);
+    }
+  })
+);
 app.use(morgan('dev'));
 app.use((req, res, next) => {
   res.header('Access-Control-Allow-Origin', '*');
-  res.header('Access-Control-Allow-Headers', 'content-type,x-api-key');
+  res.header(
+    'Access-Control-Allow-Headers',
+    'content-type,x-api-key,x-timestamp,x-nonce,x-signature,idempotency-key,x-protocol-version'
+  );
   res.header('Access-Control-Allow-Methods', 'GET,POST,OPTIONS');
   if (req.method === 'OPTIONS') {
     return res.sendStatus(200);
@@ -23,9 +36,19 @@ app.get('/health', (_req, res) => {
   res.json({ status: 'ok', network: config.bitcoinNetworkFlag });
 });
 
-app.use('/mint', mintRouter);
-app.use('/vaults', vaultRouter);
-app.use('/withdraw', withdrawRouter);
+app.get('/version', (_req, res) => {
+  res.json(protocolVersionInfo());
+});
+
+app.use('/mint', requireProtocolVersion, requireCanisterSignature, idempotentRequest, mintRouter);
+app.use('/vaults', requireProtocolVersion, requireCanisterSignature, vaultRouter);
+app.use(
+  '/withdraw',
+  requireProtocolVersion,
+  requireCanisterSignature,
+  idempotentRequest,
+  withdrawRouter
+);
 
 app.use((err: any, _req: Request, res: Response, _next: NextFunction) => {
   console.error('Unhandled error', err);
@@ -37,6 +37,31 @@ const addressBindingSchema = z
     }
   });
 
+// Versions of the canister's outputsOverride payload this backend understands.
+const OUTPUTS_OVERRIDE_VERSION = 1;
+
+const outputsOverrideSchema = z
+  .object({
+    version: z.literal(OUTPUTS_OVERRIDE_VERSION),
+    outputs: z
+      .array(
+        z.object({
+          role: z.enum(['ordinals', 'fee_recipient', 'vault']),
+          sats: z.number().int().positive().max(21_000_000 * SATS_PER_BTC)
+        })
+      )
+      .max(3)
+  })
+  .superRefine((v, ctx) => {
+    const roles = v.outputs.map((output) => output.role);
+    if (new Set(roles).size !== roles.length) {
+      ctx.addIssue({
+        code: z.ZodIssueCode.custom,
+        message: 'each output role may appear once'
+      });
+    }
+  });
+
 const mintRequestSchema = z.object({
   rune: z.string().min(1),
   feeRate: z.number().positive(),
@@ -59,7 +84,36 @@ const mintRequestSchema = z.object({
       vaultSats: z.number().int().positive()
     })
     .partial()
-    .nullish()
+    .nullish(),
+  outputsOverride: outputsOverrideSchema.nullish(),
+  changeSplit: z
+    .object({
+      maxOutputs: z.number().int().min(2).max(10),
+      targetOutputSats: z.number().int().positive()
+    })
+    .nullish(),
+  spendUnconfirmed: z
+    .object({
+      maxAncestors: z.number().int().min(1).max(25)
+    })
+    .nullish(),
+  excludeOutpoints: z
+    .array(
+      z.object({
+        txid: z.string().regex(/^[0-9a-fA-F]{64}$/, 'txid must be 32-byte hex'),
+        vout: z.number().int().nonnegative()
+      })
+    )
+    .max(1000)
+    .nullish(),
+  coinSelection: z
+    .object({
+      strategy: z.enum(['largest_first', 'branch_and_bound', 'single_random_draw']),
+      dustThresholdSats: z.number().int().min(294).optional()
+    })
+    .nullish(),
+  recoveryCsvBlocks: z.number().int().min(144).max(65535).nullish(),
+  feeSubsidySats: z.number().int().nonnegative().nullish()
 });
 
 router.use((req, res, next) => {
@@ -86,14 +140,15 @@ router.post('/build-psbt', async (req, res) => {
     const parsed = parseResult.data;
     const payload: MintRequestBody = {
       ...parsed,
-      amounts: parsed.amounts ?? undefined
+      amounts: parsed.amounts ?? undefined,
+      outputsOverride: parsed.outputsOverride ?? undefined
     };
     console.info('[mint:build-psbt] request accepted', {
       rune: payload.rune,
       feeRate: payload.feeRate,
       ordinalsAddress: payload.ordinals.address,
       paymentAddress: payload.payment.address,
-      amountsProvided: Boolean(payload.amounts),
+      amountsProvided: Boolean(payload.amounts || payload.outputsOverride),
       vaultId: payload.vaultId
     });
     const result = await buildMintPsbt(payload);
@@ -138,6 +193,7 @@ const finalizeVaultSchema = z.object({
   mintTokens: z.number().positive(),
   mintUsdCents: z.number().int().positive(),
   btcPriceUsd: z.number().positive(),
+  quoteAsset: z.string().min(1).optional(),
 });
 
 const finalizeSchema = z.object({
@@ -148,6 +204,45 @@ const finalizeSchema = z.object({
   vault: finalizeVaultSchema.optional(),
 });
 
+type FinalizedVault = z.infer<typeof finalizeVaultSchema>;
+
+async function recordBroadcastVault(vaultId: string, vault: FinalizedVault | undefined, txid: string) {
+  if (!vault) {
+    await vaultStore.setTxId(vaultId, txid);
+    return;
+  }
+  const lockedCollateralBtc = vault.collateralSats / SATS_PER_BTC;
+  const mintedUsd = vault.mintUsdCents / 100;
+  const collateralUsd = lockedCollateralBtc * vault.btcPriceUsd;
+  const collateralRatioBps =
+    mintedUsd > 0 ? Math.round((collateralUsd / mintedUsd) * 10_000) : undefined;
+  await vaultStore.recordVault({
+    vaultId,
+    protocolPublicKey: vault.protocolPublicKey,
+    protocolChainCode: vault.protocolChainCode,
+    vaultAddress: vault.vaultAddress,
+    descriptor: vault.descriptor,
+    collateralSats: vault.collateralSats,
+    lockedCollateralBtc,
+    minConfirmations: config.vaultMinConfirmations,
+    confirmations: 0,
+    withdrawable: false,
+    lastBtcPriceUsd: vault.btcPriceUsd,
+    collateralRatioBps,
+    health: 'pending',
+    metadata: {
+      rune: vault.rune,
+      feeRate: vault.feeRate,
+      ordinalsAddress: vault.ordinalsAddress,
+      paymentAddress: vault.paymentAddress,
+      mintTokens: vault.mintTokens,
+      mintUsdCents: vault.mintUsdCents,
+      quoteAsset: vault.quoteAsset === 'USD' ? undefined : vault.quoteAsset,
+    },
+    txid,
+  });
+}
+
 router.post('/finalize', async (req, res) => {
   const parsed = finalizeSchema.safeParse(req.body);
   if (!parsed.success) {
@@ -179,38 +274,8 @@ router.post('/finalize', async (req, res) => {
     let txid: string | undefined;
     if (broadcast !== false) {
       txid = await runCliRaw(['sendrawtransaction', hex]);
-      if (vault && txid) {
-        const lockedCollateralBtc = vault.collateralSats / SATS_PER_BTC;
-        const mintedUsd = vault.mintUsdCents / 100;
-        const collateralUsd = lockedCollateralBtc * vault.btcPriceUsd;
-        const collateralRatioBps =
-          mintedUsd > 0 ? Math.round((collateralUsd / mintedUsd) * 10_000) : undefined;
-        await vaultStore.recordVault({
-          vaultId,
-          protocolPublicKey: vault.protocolPublicKey,
-          protocolChainCode: vault.protocolChainCode,
-          vaultAddress: vault.vaultAddress,
-          descriptor: vault.descriptor,
-          collateralSats: vault.collateralSats,
-          lockedCollateralBtc,
-          minConfirmations: config.vaultMinConfirmations,
-          confirmations: 0,
-          withdrawable: false,
-          lastBtcPriceUsd: vault.btcPriceUsd,
-          collateralRatioBps,
-          health: 'pending',
-          metadata: {
-            rune: vault.rune,
-            feeRate: vault.feeRate,
-            ordinalsAddress: vault.ordinalsAddress,
-            paymentAddress: vault.paymentAddress,
-            mintTokens: vault.mintTokens,
-            mintUsdCents: vault.mintUsdCents,
-          },
-          txid,
-        });
-      } else if (txid) {
-        await vaultStore.setTxId(vaultId, txid);
+      if (txid) {
+        await recordBroadcastVault(vaultId, vault, txid);
       }
     }
 
@@ -220,3 +285,60 @@ router.post('/finalize', async (req, res) => {
     res.status(500).json({ error: 'FINALIZE_FAILED', message: error?.message, stdout: error?.stdout, stderr: error?.stderr });
   }
 });
+
+// --- broadcast of a canister-validated transaction ---
+const broadcastSchema = z.object({
+  vaultId: z.string().min(1),
+  hex: z.string().regex(/^[0-9a-fA-F]+$/, 'hex must be a hex string'),
+  txid: z.string().regex(/^[0-9a-fA-F]{64}$/, 'txid must be 32-byte hex'),
+  // false when the canister already broadcast through the Bitcoin canister
+  broadcast: z.boolean().optional().default(true),
+  vault: finalizeVaultSchema.optional(),
+});
+
+router.post('/broadcast', async (req, res) => {
+  const parsed = broadcastSchema.safeParse(req.body);
+  if (!parsed.success) {
+    return res.status(400).json({ error: 'INVALID_REQUEST', details: parsed.error.flatten() });
+  }
+  const { vaultId, hex, broadcast, vault } = parsed.data;
+  try {
+    const txid = broadcast ? (await runCliRaw(['sendrawtransaction', hex])).trim() : parsed.data.txid;
+    console.info('[mint:broadcast] recorded', { vaultId, txid, broadcast });
+    await recordBroadcastVault(vaultId, vault, txid);
+    res.json({ vaultId, txid });
+  } catch (error: any) {
+    console.error('[mint:broadcast] error', { message: error?.message, stdout: error?.stdout, stderr: error?.stderr });
+    res.status(500).json({ error: 'BROADCAST_FAILED', message: error?.message, stdout: error?.stdout, stderr: error?.stderr });
+  }
+});
+
+// --- fee bump of a stuck funding transaction ---
+const bumpFeeSchema = z.object({
+  wallet: z.string().min(1),
+  vaultId: z.string().min(1),
+  // unsigned replacement built by the canister
+  hex: z.string().regex(/^[0-9a-fA-F]+$/, 'hex must be a hex string'),
+});
+
+router.post('/bump-fee', async (req, res) => {
+  const parsed = bumpFeeSchema.safeParse(req.body);
+  if (!parsed.success) {
+    return res.status(400).json({ error: 'INVALID_REQUEST', details: parsed.error.flatten() });
+  }
+  const { wallet, vaultId, hex } = parsed.data;
+  try {
+    const psbt = await runCliRaw(['converttopsbt', hex]);
+    // The inputs are spent by the unconfirmed original, so take their UTXO
+    // data from the watch-only wallet rather than the UTXO set.
+    const processed = await runCliJson<{ psbt: string; complete: boolean }>(
+      ['walletprocesspsbt', psbt.trim(), 'false'],
+      { wallet }
+    );
+    console.info('[mint:bump-fee] replacement psbt built', { vaultId, wallet });
+    res.json({ vaultId, psbt: processed.psbt });
+  } catch (error: any) {
+    console.error('[mint:bump-fee] error', { message: error?.message, stdout: error?.stdout, stderr: error?.stderr });
+    res.status(500).json({ error: 'BUMP_FEE_FAILED', message: error?.message, stdout: error?.stdout, stderr: error?.stderr });
+  }
+});
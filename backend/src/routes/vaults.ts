@@ -1,4 +1,5 @@
 import { Router } from 'express';
+import { z } from 'zod';
 import { config } from '../config.js';
 import { vaultStore } from '../services/vaultStore.js';
 import { refreshVaults } from '../services/vaultHealth.js';
@@ -48,4 +49,25 @@ router.get('/', async (req, res) => {
   }
 });
 
+const redactSchema = z.object({
+  vaultId: z.string().min(1)
+});
+
+router.post('/redact', async (req, res) => {
+  const parsed = redactSchema.safeParse(req.body);
+  if (!parsed.success) {
+    return res.status(400).json({ error: 'INVALID_REQUEST', details: parsed.error.flatten() });
+  }
+  const { vaultId } = parsed.data;
+  try {
+    // unknown vaults were never mirrored here, so there is nothing to strip
+    const redacted = await vaultStore.redactVault(vaultId);
+    console.info('[vaults:redact] done', { vaultId, redacted });
+    res.json({ vaultId, redacted });
+  } catch (error: any) {
+    console.error('[vaults:redact] failed', { vaultId, message: error?.message });
+    res.status(500).json({ error: 'VAULT_REDACT_FAILED', message: error?.message });
+  }
+});
+
 export default router;
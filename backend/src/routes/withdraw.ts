@@ -1,6 +1,8 @@
 import { Router } from 'express';
 import { z } from 'zod';
-import { config } from '../config.js';
+import { config, SATS_PER_BTC } from '../config.js';
+import { vaultStore } from '../services/vaultStore.js';
+import { runCliJson, runCliRaw } from '../utils/bitcoinCli.js';
 import {
   prepareWithdraw,
   requestProtocolSignature,
@@ -82,4 +84,203 @@ router.post('/finalize', async (req, res) => {
   }
 });
 
+const broadcastSchema = z.object({
+  vaultId: z.string().min(1),
+  hex: z.string().regex(/^[0-9a-fA-F]+$/, 'hex must be a hex string'),
+  txid: z.string().regex(/^[0-9a-fA-F]{64}$/, 'txid must be 32-byte hex'),
+  // false when the canister already broadcast through the Bitcoin canister
+  broadcast: z.boolean().optional().default(true),
+  // partial releases re-lock the remaining collateral in a new vault output
+  partial: z.boolean().optional().default(false)
+});
+
+router.post('/broadcast', async (req, res) => {
+  const parsed = broadcastSchema.safeParse(req.body);
+  if (!parsed.success) {
+    return res.status(400).json({ error: 'INVALID_REQUEST', details: parsed.error.flatten() });
+  }
+  const { vaultId, hex, broadcast, partial } = parsed.data;
+  try {
+    const txid = broadcast ? (await runCliRaw(['sendrawtransaction', hex])).trim() : parsed.data.txid;
+    console.info('[withdraw] broadcast recorded', { vaultId, txid, broadcast, partial });
+    if (partial) {
+      await vaultStore.setTxId(vaultId, txid);
+    } else {
+      await vaultStore.setWithdrawTxId(vaultId, txid);
+    }
+    res.json({ vaultId, txid });
+  } catch (error: any) {
+    console.error('[withdraw:broadcast] error', { message: error?.message });
+    res.status(500).json({ error: 'WITHDRAW_BROADCAST_FAILED', message: error?.message });
+  }
+});
+
+// --- child-pays-for-parent for a stuck withdrawal ---
+const cpfpSchema = z.object({
+  vaultId: z.string().min(1),
+  // unsigned child built by the canister
+  hex: z.string().regex(/^[0-9a-fA-F]+$/, 'hex must be a hex string'),
+  // true when the child spends the re-locked vault output
+  spendsVault: z.boolean()
+});
+
+router.post('/cpfp', async (req, res) => {
+  const parsed = cpfpSchema.safeParse(req.body);
+  if (!parsed.success) {
+    return res.status(400).json({ error: 'INVALID_REQUEST', details: parsed.error.flatten() });
+  }
+  const { vaultId, hex, spendsVault } = parsed.data;
+  try {
+    const record = await vaultStore.getVault(vaultId);
+    if (!record) {
+      return res.status(404).json({ error: 'VAULT_NOT_FOUND' });
+    }
+    const wallet = spendsVault ? `vault-${vaultId}` : record.metadata.paymentAddress;
+    const psbt = await runCliRaw(['converttopsbt', hex]);
+    const processed = await runCliJson<{ psbt: string; complete: boolean }>(
+      ['walletprocesspsbt', psbt.trim(), 'false'],
+      { wallet }
+    );
+    console.info('[withdraw:cpfp] child psbt built', { vaultId, wallet, spendsVault });
+    res.json({ vaultId, psbt: processed.psbt });
+  } catch (error: any) {
+    console.error('[withdraw:cpfp] error', { message: error?.message, stdout: error?.stdout, stderr: error?.stderr });
+    res.status(500).json({ error: 'CPFP_FAILED', message: error?.message });
+  }
+});
+
+const cpfpBroadcastSchema = z.object({
+  vaultId: z.string().min(1),
+  hex: z.string().regex(/^[0-9a-fA-F]+$/, 'hex must be a hex string'),
+  txid: z.string().regex(/^[0-9a-fA-F]{64}$/, 'txid must be 32-byte hex'),
+  broadcast: z.boolean().optional().default(true),
+  parentTxid: z.string().regex(/^[0-9a-fA-F]{64}$/, 'parentTxid must be 32-byte hex'),
+  spendsVault: z.boolean()
+});
+
+router.post('/cpfp-broadcast', async (req, res) => {
+  const parsed = cpfpBroadcastSchema.safeParse(req.body);
+  if (!parsed.success) {
+    return res.status(400).json({ error: 'INVALID_REQUEST', details: parsed.error.flatten() });
+  }
+  const { vaultId, hex, broadcast, parentTxid, spendsVault } = parsed.data;
+  try {
+    const txid = broadcast ? (await runCliRaw(['sendrawtransaction', hex])).trim() : parsed.data.txid;
+    console.info('[withdraw:cpfp] broadcast recorded', { vaultId, txid, parentTxid, broadcast });
+    if (spendsVault) {
+      // the child now holds the re-locked collateral
+      await vaultStore.setTxId(vaultId, txid);
+    }
+    res.json({ vaultId, txid });
+  } catch (error: any) {
+    console.error('[withdraw:cpfp-broadcast] error', { message: error?.message });
+    res.status(500).json({ error: 'CPFP_BROADCAST_FAILED', message: error?.message });
+  }
+});
+
+// Redemptions spend several vaults in one transaction: vaults whose debt was
+// fully redeemed close, the rest re-lock their remaining collateral.
+const redeemBroadcastSchema = z.object({
+  hex: z.string().regex(/^[0-9a-fA-F]+$/, 'hex must be a hex string'),
+  txid: z.string().regex(/^[0-9a-fA-F]{64}$/, 'txid must be 32-byte hex'),
+  broadcast: z.boolean().optional().default(true),
+  vaults: z
+    .array(
+      z.object({
+        vaultId: z.string().min(1),
+        closed: z.boolean()
+      })
+    )
+    .min(1)
+});
+
+router.post('/redeem-broadcast', async (req, res) => {
+  const parsed = redeemBroadcastSchema.safeParse(req.body);
+  if (!parsed.success) {
+    return res.status(400).json({ error: 'INVALID_REQUEST', details: parsed.error.flatten() });
+  }
+  const { hex, broadcast, vaults } = parsed.data;
+  try {
+    const txid = broadcast ? (await runCliRaw(['sendrawtransaction', hex])).trim() : parsed.data.txid;
+    console.info('[withdraw:redeem] broadcast recorded', { txid, broadcast, vaults: vaults.length });
+    for (const vault of vaults) {
+      if (vault.closed) {
+        await vaultStore.setWithdrawTxId(vault.vaultId, txid);
+      } else {
+        await vaultStore.setTxId(vault.vaultId, txid);
+      }
+    }
+    res.json({ txid });
+  } catch (error: any) {
+    console.error('[withdraw:redeem] error', { message: error?.message });
+    res.status(500).json({ error: 'REDEEM_BROADCAST_FAILED', message: error?.message });
+  }
+});
+
+// Auction settlements pay a liquidated vault's collateral to the winning
+// bidder (and any surplus to the owner); the vault closes.
+const liquidateBroadcastSchema = z.object({
+  vaultId: z.string().min(1),
+  hex: z.string().regex(/^[0-9a-fA-F]+$/, 'hex must be a hex string'),
+  txid: z.string().regex(/^[0-9a-fA-F]{64}$/, 'txid must be 32-byte hex'),
+  broadcast: z.boolean().optional().default(true)
+});
+
+router.post('/liquidate-broadcast', async (req, res) => {
+  const parsed = liquidateBroadcastSchema.safeParse(req.body);
+  if (!parsed.success) {
+    return res.status(400).json({ error: 'INVALID_REQUEST', details: parsed.error.flatten() });
+  }
+  const { vaultId, hex, broadcast } = parsed.data;
+  try {
+    const txid = broadcast ? (await runCliRaw(['sendrawtransaction', hex])).trim() : parsed.data.txid;
+    console.info('[withdraw:liquidate] broadcast recorded', { vaultId, txid, broadcast });
+    await vaultStore.setWithdrawTxId(vaultId, txid);
+    res.json({ vaultId, txid });
+  } catch (error: any) {
+    console.error('[withdraw:liquidate] error', { message: error?.message });
+    res.status(500).json({ error: 'LIQUIDATE_BROADCAST_FAILED', message: error?.message });
+  }
+});
+
+// Key migrations move a vault's whole collateral to the output derived from
+// the canister's new threshold key; the vault keeps its id and debt.
+const migrateBroadcastSchema = z.object({
+  vaultId: z.string().min(1),
+  hex: z.string().regex(/^[0-9a-fA-F]+$/, 'hex must be a hex string'),
+  txid: z.string().regex(/^[0-9a-fA-F]{64}$/, 'txid must be 32-byte hex'),
+  broadcast: z.boolean().optional().default(true),
+  vaultAddress: z.string().min(1),
+  descriptor: z.string().min(1),
+  protocolPublicKey: z.string().regex(/^[0-9a-fA-F]{64}$/, 'protocolPublicKey must be 32-byte x-only hex'),
+  protocolChainCode: z.string().regex(/^[0-9a-fA-F]{64}$/, 'protocolChainCode must be 32-byte hex'),
+  collateralSats: z.number().int().positive()
+});
+
+router.post('/migrate-broadcast', async (req, res) => {
+  const parsed = migrateBroadcastSchema.safeParse(req.body);
+  if (!parsed.success) {
+    return res.status(400).json({ error: 'INVALID_REQUEST', details: parsed.error.flatten() });
+  }
+  const { vaultId, hex, broadcast, collateralSats, ...keys } = parsed.data;
+  try {
+    const txid = broadcast ? (await runCliRaw(['sendrawtransaction', hex])).trim() : parsed.data.txid;
+    console.info('[withdraw:migrate] broadcast recorded', { vaultId, txid, broadcast, vaultAddress: keys.vaultAddress });
+    await vaultStore.updateVault(vaultId, {
+      vaultAddress: keys.vaultAddress,
+      descriptor: keys.descriptor,
+      protocolPublicKey: keys.protocolPublicKey,
+      protocolChainCode: keys.protocolChainCode,
+      collateralSats,
+      lockedCollateralBtc: collateralSats / SATS_PER_BTC,
+      confirmations: 0,
+      txid
+    });
+    res.json({ vaultId, txid });
+  } catch (error: any) {
+    console.error('[withdraw:migrate] error', { message: error?.message });
+    res.status(500).json({ error: 'MIGRATE_BROADCAST_FAILED', message: error?.message });
+  }
+});
+
 export default router;
@@ -0,0 +1,92 @@
+import assert from 'node:assert/strict';
+import { describe, it } from 'node:test';
+import { finalizeSelection, selectCoins, SelectableUtxo } from './coinSelection.js';
+
+const target = {
+  targetSats: 10_000,
+  feeRate: 1,
+  baseVbytes: 50,
+  inputVbytes: 68,
+  changeVbytes: 31,
+  dustThresholdSats: 546
+};
+
+function utxos(...amounts: number[]): SelectableUtxo[] {
+  return amounts.map((valueSats, vout) => ({ txid: 'aa'.repeat(32), vout, valueSats }));
+}
+
+function values(inputs: SelectableUtxo[]): number[] {
+  return inputs.map((utxo) => utxo.valueSats).sort((a, b) => a - b);
+}
+
+function total(inputs: SelectableUtxo[]): number {
+  return inputs.reduce((sum, utxo) => sum + utxo.valueSats, 0);
+}
+
+describe('largest_first', () => {
+  it('spends the largest UTXO first and returns change', () => {
+    const result = selectCoins(utxos(5_000, 20_000, 12_000), target, 'largest_first');
+    assert.equal(result.strategy, 'largest_first');
+    assert.deepEqual(values(result.inputs), [20_000]);
+    // 50 base + 68 input + 31 change vbytes at 1 sat/vB
+    assert.equal(result.feeSats, 149);
+    assert.equal(result.changeSats, 9_851);
+  });
+
+  it('adds change below the dust threshold to the fee', () => {
+    const result = selectCoins(utxos(10_200), target, 'largest_first');
+    assert.equal(result.changeSats, 0);
+    assert.equal(result.feeSats, 200);
+  });
+
+  it('never spends UTXOs worth less than their input fee', () => {
+    const result = selectCoins(utxos(60, 11_000), target, 'largest_first');
+    assert.deepEqual(values(result.inputs), [11_000]);
+  });
+
+  it('rejects when the spendable UTXOs cannot cover the target', () => {
+    assert.throws(
+      () => selectCoins(utxos(4_000, 5_000), target, 'largest_first'),
+      /Insufficient funds/
+    );
+  });
+});
+
+describe('branch_and_bound', () => {
+  it('finds a changeless match instead of the largest UTXO', () => {
+    const result = selectCoins(utxos(30_000, 6_100, 4_100), target, 'branch_and_bound');
+    assert.equal(result.strategy, 'branch_and_bound');
+    assert.deepEqual(values(result.inputs), [4_100, 6_100]);
+    assert.equal(result.changeSats, 0);
+    assert.equal(result.feeSats, 200);
+  });
+
+  it('falls back to largest-first without a changeless match', () => {
+    const result = selectCoins(utxos(30_000), target, 'branch_and_bound');
+    assert.equal(result.strategy, 'largest_first');
+    assert.deepEqual(values(result.inputs), [30_000]);
+  });
+});
+
+describe('single_random_draw', () => {
+  it('draws until the target and fee are covered', () => {
+    for (let run = 0; run < 20; run += 1) {
+      const result = selectCoins(utxos(3_000, 4_000, 5_000, 6_000), target, 'single_random_draw');
+      assert.equal(result.strategy, 'single_random_draw');
+      assert.equal(total(result.inputs), target.targetSats + result.feeSats + result.changeSats);
+      assert.ok(result.changeSats === 0 || result.changeSats >= target.dustThresholdSats);
+    }
+  });
+});
+
+describe('finalizeSelection', () => {
+  it('reprices fixed inputs at a higher fee rate', () => {
+    const bumped = finalizeSelection('largest_first', utxos(20_000), { ...target, feeRate: 10 });
+    assert.equal(bumped?.feeSats, 1_490);
+    assert.equal(bumped?.changeSats, 8_510);
+    assert.equal(
+      finalizeSelection('largest_first', utxos(10_200), { ...target, feeRate: 10 }),
+      undefined
+    );
+  });
+});
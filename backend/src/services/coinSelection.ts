@@ -0,0 +1,178 @@
+import { randomInt } from 'node:crypto';
+import type { CoinSelectionStrategy } from '../types.js';
+
+export interface SelectableUtxo {
+  txid: string;
+  vout: number;
+  valueSats: number;
+}
+
+export interface CoinSelectionTarget {
+  /** Sum of all non-change outputs. */
+  targetSats: number;
+  /** sat/vB */
+  feeRate: number;
+  /** Transaction overhead plus the non-change outputs. */
+  baseVbytes: number;
+  inputVbytes: number;
+  changeVbytes: number;
+  /** Change below this is dropped to fees instead of creating an output. */
+  dustThresholdSats: number;
+}
+
+export interface CoinSelectionResult {
+  strategy: CoinSelectionStrategy;
+  inputs: SelectableUtxo[];
+  feeSats: number;
+  changeSats: number;
+}
+
+export const DEFAULT_DUST_THRESHOLD_SATS = 546;
+
+// Bounds the branch-and-bound search; past this it falls back to largest-first.
+const BNB_MAX_TRIES = 100_000;
+
+function effectiveValue(utxo: SelectableUtxo, target: CoinSelectionTarget): number {
+  return utxo.valueSats - Math.ceil(target.inputVbytes * target.feeRate);
+}
+
+/**
+ * Computes fee and change for a fixed input set, dropping change below the dust
+ * threshold. Returns undefined when the inputs cannot cover outputs plus fee.
+ */
+export function finalizeSelection(
+  strategy: CoinSelectionStrategy,
+  inputs: SelectableUtxo[],
+  target: CoinSelectionTarget
+): CoinSelectionResult | undefined {
+  const total = inputs.reduce((sum, utxo) => sum + utxo.valueSats, 0);
+  const vbytesNoChange = target.baseVbytes + inputs.length * target.inputVbytes;
+  const feeNoChange = Math.ceil(vbytesNoChange * target.feeRate);
+  const feeWithChange = Math.ceil((vbytesNoChange + target.changeVbytes) * target.feeRate);
+  const changeSats = total - target.targetSats - feeWithChange;
+  if (changeSats >= target.dustThresholdSats) {
+    return { strategy, inputs, feeSats: feeWithChange, changeSats };
+  }
+  if (total - target.targetSats < feeNoChange) {
+    return undefined;
+  }
+  return { strategy, inputs, feeSats: total - target.targetSats, changeSats: 0 };
+}
+
+function accumulate(
+  strategy: CoinSelectionStrategy,
+  ordered: SelectableUtxo[],
+  target: CoinSelectionTarget
+): CoinSelectionResult | undefined {
+  const selected: SelectableUtxo[] = [];
+  for (const utxo of ordered) {
+    selected.push(utxo);
+    const result = finalizeSelection(strategy, selected, target);
+    if (result) {
+      return result;
+    }
+  }
+  return undefined;
+}
+
+function largestFirst(utxos: SelectableUtxo[], target: CoinSelectionTarget) {
+  const ordered = [...utxos].sort((a, b) => b.valueSats - a.valueSats);
+  return accumulate('largest_first', ordered, target);
+}
+
+function singleRandomDraw(utxos: SelectableUtxo[], target: CoinSelectionTarget) {
+  const shuffled = [...utxos];
+  for (let i = shuffled.length - 1; i > 0; i -= 1) {
+    const j = randomInt(i + 1);
+    [shuffled[i], shuffled[j]] = [shuffled[j], shuffled[i]];
+  }
+  return accumulate('single_random_draw', shuffled, target);
+}
+
+/**
+ * Depth-first search for an input set whose effective value lands between the
+ * target and the target plus the cost of a change output, so no change is made.
+ */
+function branchAndBound(utxos: SelectableUtxo[], target: CoinSelectionTarget) {
+  const pool = [...utxos].sort((a, b) => effectiveValue(b, target) - effectiveValue(a, target));
+  const values = pool.map((utxo) => effectiveValue(utxo, target));
+  const lower = target.targetSats + Math.ceil(target.baseVbytes * target.feeRate);
+  const costOfChange =
+    Math.ceil((target.changeVbytes + target.inputVbytes) * target.feeRate) +
+    target.dustThresholdSats;
+  const upper = lower + costOfChange;
+
+  const remainingAfter = new Array<number>(values.length + 1).fill(0);
+  for (let i = values.length - 1; i >= 0; i -= 1) {
+    remainingAfter[i] = remainingAfter[i + 1] + values[i];
+  }
+
+  let tries = 0;
+  let best: number[] | undefined;
+  let bestWaste = Number.POSITIVE_INFINITY;
+  const chosen: number[] = [];
+
+  const search = (index: number, sum: number) => {
+    tries += 1;
+    if (tries > BNB_MAX_TRIES || sum > upper) {
+      return;
+    }
+    if (sum >= lower) {
+      const waste = sum - lower;
+      if (waste < bestWaste) {
+        best = [...chosen];
+        bestWaste = waste;
+      }
+      return;
+    }
+    if (index >= values.length || sum + remainingAfter[index] < lower) {
+      return;
+    }
+    chosen.push(index);
+    search(index + 1, sum + values[index]);
+    chosen.pop();
+    search(index + 1, sum);
+  };
+  search(0, 0);
+
+  if (!best) {
+    return undefined;
+  }
+  return finalizeSelection(
+    'branch_and_bound',
+    best.map((i) => pool[i]),
+    target
+  );
+}
+
+/**
+ * Picks mint inputs with `strategy`. UTXOs that cost more to spend than they
+ * are worth at the target fee rate are never selected. Branch-and-bound falls
+ * back to largest-first when no changeless match exists.
+ */
+export function selectCoins(
+  utxos: SelectableUtxo[],
+  target: CoinSelectionTarget,
+  strategy: CoinSelectionStrategy
+): CoinSelectionResult {
+  const spendable = utxos.filter((utxo) => effectiveValue(utxo, target) > 0);
+  let result: CoinSelectionResult | undefined;
+  switch (strategy) {
+    case 'branch_and_bound':
+      result = branchAndBound(spendable, target) ?? largestFirst(spendable, target);
+      break;
+    case 'single_random_draw':
+      result = singleRandomDraw(spendable, target);
+      break;
+    case 'largest_first':
+    default:
+      result = largestFirst(spendable, target);
+  }
+  if (!result) {
+    const available = spendable.reduce((sum, utxo) => sum + utxo.valueSats, 0);
+    throw new Error(
+      `Insufficient funds: ${available} sats spendable, ${target.targetSats} sats plus fees required`
+    );
+  }
+  return result;
+}
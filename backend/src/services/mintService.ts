@@ -1,6 +1,27 @@
-import { config, satsToBtcString } from '../config.js';
-import { MintOutputAmounts, MintPsbtResult, MintRequestBody } from '../types.js';
+import { config, SATS_PER_BTC, satsToBtcString } from '../config.js';
+import {
+  ChangeSplitPolicy,
+  MintOutputAmounts,
+  MintOutputRole,
+  MintPsbtResult,
+  MintRequestBody,
+  Outpoint,
+  UnconfirmedAncestors
+} from '../types.js';
 import { runCliJson, runCliRaw } from '../utils/bitcoinCli.js';
+import {
+  CoinSelectionResult,
+  CoinSelectionTarget,
+  DEFAULT_DUST_THRESHOLD_SATS,
+  finalizeSelection,
+  selectCoins
+} from './coinSelection.js';
+import {
+  childFeeRateForPackage,
+  isNotInMempool,
+  packageFeeRate,
+  untrustedUnconfirmed
+} from './unconfirmedSpend.js';
 import { vaultStore } from './vaultStore.js';
 
 interface DescriptorInfo {
@@ -29,9 +50,129 @@ interface DecodedPsbtVout {
 
 interface DecodedPsbt {
   tx: {
+    vsize?: number;
     vin: DecodedPsbtVin[];
     vout: DecodedPsbtVout[];
   };
+  fee?: number;
+}
+
+interface ListUnspentEntry {
+  txid: string;
+  vout: number;
+  amount: number;
+  confirmations: number;
+}
+
+interface MempoolEntry {
+  ancestorcount: number;
+  ancestorsize: number;
+  fees: { ancestor: number };
+}
+
+/**
+ * Sums the mempool ancestry of every unconfirmed input. Inputs sharing ancestors
+ * are counted once per input, which overstates the package and keeps the fee
+ * check conservative. Confirmed inputs have no mempool entry and are skipped;
+ * any other node error fails the build.
+ */
+async function collectUnconfirmedAncestors(txids: string[]): Promise<UnconfirmedAncestors> {
+  const totals: UnconfirmedAncestors = { count: 0, vsize: 0, feeSats: 0 };
+  for (const txid of new Set(txids)) {
+    let entry: MempoolEntry;
+    try {
+      entry = await runCliJson<MempoolEntry>(['getmempoolentry', txid]);
+    } catch (e) {
+      if (isNotInMempool(e)) {
+        continue;
+      }
+      throw e;
+    }
+    totals.count += entry.ancestorcount;
+    totals.vsize += entry.ancestorsize;
+    totals.feeSats += Math.round(entry.fees.ancestor * SATS_PER_BTC);
+  }
+  return totals;
+}
+
+/** Transactions the canister built for `paymentAddress`, whose change it may spend unconfirmed. */
+async function canisterTxids(paymentAddress: string): Promise<Set<string>> {
+  const vaults = await vaultStore.listVaultsByPayment(paymentAddress);
+  return new Set(
+    vaults
+      .flatMap((vault) => [vault.txid, vault.withdrawTxId])
+      .filter((txid): txid is string => Boolean(txid))
+      .map((txid) => txid.toLowerCase())
+  );
+}
+
+/**
+ * Locks the outpoints reserved by other pending mints so walletcreatefundedpsbt
+ * skips them, along with unconfirmed outputs of transactions the canister did
+ * not build when `trusted` is given. Only outpoints the wallet can spend are
+ * locked (lockunspent rejects the rest); the returned list must be unlocked
+ * once funding is done.
+ */
+async function lockReservedOutpoints(
+  wallet: string,
+  reserved: Outpoint[],
+  trusted?: Set<string>
+): Promise<Outpoint[]> {
+  if (reserved.length === 0 && !trusted) {
+    return [];
+  }
+  const unspent = await runCliJson<ListUnspentEntry[]>(
+    ['listunspent', '0', '9999999', '[]', 'true'],
+    { wallet }
+  );
+  const spendable = new Set(unspent.map((utxo) => `${utxo.txid}:${utxo.vout}`));
+  const excluded = new Map<string, Outpoint>();
+  for (const { txid, vout } of reserved) {
+    excluded.set(`${txid.toLowerCase()}:${vout}`, { txid: txid.toLowerCase(), vout });
+  }
+  for (const outpoint of trusted ? untrustedUnconfirmed(unspent, trusted) : []) {
+    excluded.set(`${outpoint.txid}:${outpoint.vout}`, outpoint);
+  }
+  const toLock = Array.from(excluded.values()).filter(({ txid, vout }) =>
+    spendable.has(`${txid}:${vout}`)
+  );
+  if (toLock.length > 0) {
+    await runCliJson<boolean>(['lockunspent', 'false', JSON.stringify(toLock)], { wallet });
+    console.info('[mintService] locked reserved outpoints', { wallet, count: toLock.length });
+  }
+  return toLock;
+}
+
+async function unlockOutpoints(wallet: string, locked: Outpoint[]): Promise<void> {
+  if (locked.length === 0) {
+    return;
+  }
+  try {
+    await runCliJson<boolean>(['lockunspent', 'true', JSON.stringify(locked)], { wallet });
+  } catch (e: any) {
+    console.warn('[mintService] unlockunspent warning', { wallet, message: e?.message });
+  }
+}
+
+const walletFundingQueues = new Map<string, Promise<unknown>>();
+
+/**
+ * Runs `task` once every funding already queued for `wallet` has settled. Locks
+ * are wallet-wide, so a concurrent mint could otherwise unlock outpoints this
+ * one still relies on, or fund from outpoints it has just locked.
+ */
+async function withWalletFunding<T>(wallet: string, task: () => Promise<T>): Promise<T> {
+  const previous = walletFundingQueues.get(wallet) ?? Promise.resolve();
+  const run = previous.catch(() => undefined).then(task);
+  const settled = run.catch(() => undefined);
+  walletFundingQueues.set(wallet, settled);
+  try {
+    return await run;
+  } finally {
+    if (walletFundingQueues.get(wallet) === settled) {
+      walletFundingQueues.delete(wallet);
+    }
+  }
 }
 
 function xOnly(hex: string): string {
@@ -47,7 +188,11 @@ export function sanitizeWalletName(value: string): string {
   return value.replace(/[^a-zA-Z0-9_-]/g, '_');
 }
 
-function buildDescriptor(protocolXOnly: string, userCompressed33: string): string {
+function buildDescriptor(
+  protocolXOnly: string,
+  userCompressed33: string,
+  recoveryCsvBlocks?: number | null
+): string {
   const internal = xOnly(config.guardianPublicKey);
   const userX = xOnly(userCompressed33);
   // Redemption leaf: protocol key (x-only) + user
@@ -57,6 +202,12 @@ function buildDescriptor(protocolXOnly: string, userCompressed33: string): strin
   const vkB = xOnly(config.vaultKeys[1]);
   const leafBX = `multi_a(2,${vkA},${vkB})`;
 
+  if (recoveryCsvBlocks) {
+    // Recovery leaf: the user alone, once the vault output is `recoveryCsvBlocks` deep
+    const leafCX = `and_v(v:pk(${userX}),older(${recoveryCsvBlocks}))`;
+    return `tr(${internal},{{${leafAX},${leafBX}},${leafCX}})`;
+  }
+
   // TapTree with guardian internal key and two script leaves
   return `tr(${internal},{${leafAX},${leafBX}})`;
 }
@@ -187,14 +338,38 @@ async function deriveVaultAddress(descriptorWithChecksum: string): Promise<strin
   return addresses[0];
 }
 
-function resolveAmounts(amounts?: Partial<MintOutputAmounts>): MintOutputAmounts {
+const OUTPUT_ROLE_FIELDS: Record<MintOutputRole, keyof MintOutputAmounts> = {
+  ordinals: 'ordinalsSats',
+  fee_recipient: 'feeRecipientSats',
+  vault: 'vaultSats'
+};
+
+function resolveAmounts(body: MintRequestBody): MintOutputAmounts {
+  const amounts: Partial<MintOutputAmounts> = { ...body.amounts };
+  for (const { role, sats } of body.outputsOverride?.outputs ?? []) {
+    amounts[OUTPUT_ROLE_FIELDS[role]] = sats;
+  }
   return {
-    ordinalsSats: amounts?.ordinalsSats ?? config.defaults.ordinalsSats,
-    feeRecipientSats: amounts?.feeRecipientSats ?? config.defaults.feeRecipientSats,
-    vaultSats: amounts?.vaultSats ?? config.defaults.vaultSats
+    ordinalsSats: amounts.ordinalsSats ?? config.defaults.ordinalsSats,
+    feeRecipientSats: amounts.feeRecipientSats ?? config.defaults.feeRecipientSats,
+    vaultSats: amounts.vaultSats ?? config.defaults.vaultSats
   };
 }
 
+// Protocol-pays fee mode: the subsidy comes out of the fee recipient output,
+// which is never reduced below dust. Returns the sats actually applied.
+function applyFeeSubsidy(amounts: MintOutputAmounts, subsidySats?: number | null): number {
+  if (!subsidySats) {
+    return 0;
+  }
+  const applied = Math.max(
+    0,
+    Math.min(subsidySats, amounts.feeRecipientSats - DEFAULT_DUST_THRESHOLD_SATS)
+  );
+  amounts.feeRecipientSats -= applied;
+  return applied;
+}
+
 function findOutputByAddress(
   outputs: DecodedPsbtVout[],
   targetAddress: string
@@ -208,28 +383,125 @@ function findOutputByAddress(
   });
 }
 
-function buildOutputsObject(
+// vbytes added by one extra P2TR/P2WPKH-sized change output
+const CHANGE_OUTPUT_VBYTES = 43;
+
+/**
+ * Splits a change amount into up to `maxOutputs` outputs of `targetOutputSats`,
+ * with the remainder (minus the fee for the extra outputs) in the last one, so
+ * later mints from the same payment address can each spend their own UTXO.
+ */
+export function splitChangeSats(
+  changeSats: number,
+  feeRate: number,
+  policy?: ChangeSplitPolicy | null
+): number[] {
+  if (!policy || policy.maxOutputs < 2 || policy.targetOutputSats <= 0) {
+    return [changeSats];
+  }
+  const maxCount = Math.min(policy.maxOutputs, Math.floor(changeSats / policy.targetOutputSats));
+  for (let count = maxCount; count >= 2; count--) {
+    const extraFee = Math.ceil((count - 1) * CHANGE_OUTPUT_VBYTES * feeRate);
+    const remainder = changeSats - extraFee - (count - 1) * policy.targetOutputSats;
+    if (remainder >= policy.targetOutputSats) {
+      return [...Array(count - 1).fill(policy.targetOutputSats), remainder];
+    }
+  }
+  return [changeSats];
+}
+
+function buildOutputsList(
   ordinalsAddress: string,
   feeRecipientAddress: string,
   vaultAddress: string,
   paymentAddress: string,
   amounts: MintOutputAmounts,
-  changeOutput?: DecodedPsbtVout
-): Record<string, string | number> {
-  const outputs: Record<string, string | number> = {
-    data: config.mintRunestoneData,
-    [ordinalsAddress]: Number(satsToBtcString(amounts.ordinalsSats)),
-    [feeRecipientAddress]: Number(satsToBtcString(amounts.feeRecipientSats)),
-    [vaultAddress]: Number(satsToBtcString(amounts.vaultSats))
-  };
+  changeSats: number[]
+): Array<Record<string, string | number>> {
+  // Array form so the payment address may receive several change outputs
+  const outputs: Array<Record<string, string | number>> = [
+    { data: config.mintRunestoneData },
+    { [ordinalsAddress]: satsToBtcString(amounts.ordinalsSats) },
+    { [feeRecipientAddress]: satsToBtcString(amounts.feeRecipientSats) },
+    { [vaultAddress]: satsToBtcString(amounts.vaultSats) }
+  ];
 
-  if (changeOutput) {
-    outputs[paymentAddress] = Number(changeOutput.value.toFixed(8));
+  for (const sats of changeSats) {
+    outputs.push({ [paymentAddress]: satsToBtcString(sats) });
   }
 
   return outputs;
 }
 
+// Rough per-input vbytes by payment address type, used only for coin selection.
+const INPUT_VBYTES: Record<string, number> = {
+  p2tr: 58,
+  p2wpkh: 68,
+  'p2sh-p2wpkh': 91,
+  p2sh: 91,
+  p2pkh: 148
+};
+const TX_OVERHEAD_VBYTES = 11;
+
+interface MintCoinSelection {
+  selection: CoinSelectionResult;
+  target: CoinSelectionTarget;
+}
+
+/**
+ * Chooses funding inputs and change with the configured coin-selection strategy
+ * instead of leaving it to bitcoind. Returns undefined when no strategy is
+ * configured.
+ */
+async function selectMintInputs(
+  wallet: string,
+  body: MintRequestBody,
+  amounts: MintOutputAmounts,
+  trusted?: Set<string>
+): Promise<MintCoinSelection | undefined> {
+  const policy = body.coinSelection;
+  if (!policy) {
+    return undefined;
+  }
+  const minConf = body.spendUnconfirmed ? '0' : '1';
+  const unspent = await runCliJson<ListUnspentEntry[]>(
+    ['listunspent', minConf, '9999999', '[]', String(Boolean(body.spendUnconfirmed))],
+    { wallet }
+  );
+  const untrusted = trusted ? untrustedUnconfirmed(unspent, trusted) : [];
+  const excluded = new Set(
+    [...(body.excludeOutpoints ?? []), ...untrusted].map(
+      ({ txid, vout }) => `${txid.toLowerCase()}:${vout}`
+    )
+  );
+  const utxos = unspent
+    .filter(({ txid, vout }) => !excluded.has(`${txid}:${vout}`))
+    .map(({ txid, vout, amount }) => ({
+      txid,
+      vout,
+      valueSats: Math.round(amount * SATS_PER_BTC)
+    }));
+  // ordinals, fee recipient and vault outputs plus the runestone OP_RETURN
+  const runestoneVbytes = 8 + 1 + 3 + config.mintRunestoneData.length / 2;
+  const target: CoinSelectionTarget = {
+    targetSats: amounts.ordinalsSats + amounts.feeRecipientSats + amounts.vaultSats,
+    feeRate: body.feeRate,
+    baseVbytes: TX_OVERHEAD_VBYTES + 3 * CHANGE_OUTPUT_VBYTES + runestoneVbytes,
+    inputVbytes: INPUT_VBYTES[body.payment.addressType.toLowerCase()] ?? 68,
+    changeVbytes: CHANGE_OUTPUT_VBYTES,
+    dustThresholdSats: policy.dustThresholdSats ?? DEFAULT_DUST_THRESHOLD_SATS
+  };
+  const selection = selectCoins(utxos, target, policy.strategy);
+  console.info('[mintService] coin selection', {
+    wallet,
+    strategy: selection.strategy,
+    inputs: selection.inputs.length,
+    feeSats: selection.feeSats,
+    changeSats: selection.changeSats
+  });
+  return { selection, target };
+}
+
 function patchRunestoneData(rawHex: string): string {
   const data = config.mintRunestoneData;
   const lowerHex = rawHex.toLowerCase();
@@ -321,7 +593,11 @@ export async function buildMintPsbt(body: MintRequestBody): Promise<MintPsbtResu
     console.warn('[mintService] importPaymentDescriptor warning (continuing)', { message: e?.message });
   }
 
-  const descriptor = buildDescriptor(protocolPublicKey, body.payment.publicKey);
+  const descriptor = buildDescriptor(
+    protocolPublicKey,
+    body.payment.publicKey,
+    body.recoveryCsvBlocks
+  );
   const descriptorInfo = await getDescriptorInfo(descriptor);
   const descriptorWithChecksum = descriptorInfo.descriptor; // already contains #checksum
 
@@ -339,34 +615,51 @@ export async function buildMintPsbt(body: MintRequestBody): Promise<MintPsbtResu
   const vaultAddress = await deriveVaultAddress(descriptorWithChecksum);
   console.info('[mintService] descriptor ready', { wallet, vaultWallet, vaultAddress, vaultId });
 
-  const resolvedAmounts = resolveAmounts(body.amounts);
+  const resolvedAmounts = resolveAmounts(body);
+  const feeSubsidySats = applyFeeSubsidy(resolvedAmounts, body.feeSubsidySats);
+  if (body.feeSubsidySats) {
+    console.info('[mintService] fee subsidy', {
+      requested: body.feeSubsidySats,
+      applied: feeSubsidySats,
+      feeRecipientSats: resolvedAmounts.feeRecipientSats
+    });
+  }
   const feeRecipientAddr = config.feeRecipientAddress;
-
-  async function createPsbt(): Promise<WalletCreateFundedPsbtResult> {
+  // unconfirmed outputs are only spendable when the canister built their transaction
+  const trusted = body.spendUnconfirmed ? await canisterTxids(body.payment.address) : undefined;
+  const coinSelection = await selectMintInputs(wallet, body, resolvedAmounts, trusted);
+
+  async function createPsbt(
+    feeRate: number,
+    fundingInputs: Outpoint[] | undefined
+  ): Promise<WalletCreateFundedPsbtResult> {
     console.info('[mintService] walletcreatefundedpsbt', {
       wallet,
       ordinals: body.ordinals.address,
       feeRecipient: feeRecipientAddr,
       vaultAddress,
-      feeRate: body.feeRate
+      feeRate
     });
     const out = await runCliRaw(
       [
         'walletcreatefundedpsbt',
-        '[]',
+        JSON.stringify(fundingInputs ?? []),
         JSON.stringify({
           data: config.mintRunestoneData,
-          [body.ordinals.address]: Number(satsToBtcString(resolvedAmounts.ordinalsSats)),
-          [feeRecipientAddr]: Number(satsToBtcString(resolvedAmounts.feeRecipientSats)),
-          [vaultAddress]: Number(satsToBtcString(resolvedAmounts.vaultSats))
+          [body.ordinals.address]: satsToBtcString(resolvedAmounts.ordinalsSats),
+          [feeRecipientAddr]: satsToBtcString(resolvedAmounts.feeRecipientSats),
+          [vaultAddress]: satsToBtcString(resolvedAmounts.vaultSats)
         }),
         '0',
         JSON.stringify({
           changeAddress: body.payment.address,
           changePosition: 4,
-          add_inputs: true,
+          add_inputs: !fundingInputs,
           includeWatching: true,
-          fee_rate: body.feeRate
+          // watch-only change is "untrusted" until confirmed; other unconfirmed
+          // outputs are locked before funding
+          include_unsafe: Boolean(body.spendUnconfirmed),
+          fee_rate: feeRate
         })
       ],
       { wallet }
@@ -379,27 +672,119 @@ export async function buildMintPsbt(body: MintRequestBody): Promise<MintPsbtResu
     }
   }
 
-  let psbtResult: WalletCreateFundedPsbtResult;
-  try {
-    psbtResult = await createPsbt();
-  } catch (e: any) {
-    const msg = String(e?.message ?? '').toLowerCase();
-    if (msg.includes('wallet is currently rescanning')) {
-      console.warn('[mintService] wallet rescanning detected, waiting', { wallet });
-      await waitForWalletRescan(wallet);
-      psbtResult = await createPsbt();
-    } else {
-      throw e;
+  /**
+   * Builds the PSBT with exactly the inputs and change coin selection chose;
+   * the wallet only fills in the input details. The fee is what selection left
+   * between inputs and outputs.
+   */
+  async function createSelectedPsbt(
+    selection: CoinSelectionResult
+  ): Promise<WalletCreateFundedPsbtResult> {
+    const outputs: Array<Record<string, string>> = [
+      { data: config.mintRunestoneData },
+      { [body.ordinals.address]: satsToBtcString(resolvedAmounts.ordinalsSats) },
+      { [feeRecipientAddr]: satsToBtcString(resolvedAmounts.feeRecipientSats) },
+      { [vaultAddress]: satsToBtcString(resolvedAmounts.vaultSats) }
+    ];
+    if (selection.changeSats > 0) {
+      outputs.push({ [body.payment.address]: satsToBtcString(selection.changeSats) });
     }
+    const inputs = selection.inputs.map(({ txid, vout }) => ({ txid, vout }));
+    console.info('[mintService] createpsbt from coin selection', {
+      wallet,
+      inputs: inputs.length,
+      feeSats: selection.feeSats,
+      changeSats: selection.changeSats
+    });
+    const unfunded = await runCliRaw([
+      'createpsbt',
+      JSON.stringify(inputs),
+      JSON.stringify(outputs),
+      '0',
+      'true'
+    ]);
+    const processed = await runCliJson<{ psbt: string }>(['walletprocesspsbt', unfunded, 'false'], {
+      wallet
+    });
+    return {
+      psbt: processed.psbt,
+      fee: selection.feeSats / SATS_PER_BTC,
+      changepositions: selection.changeSats > 0 ? [outputs.length - 1] : []
+    };
   }
-  console.info('[mintService] walletcreatefundedpsbt success', {
+
+  let psbtResult = await withWalletFunding(wallet, async () => {
+    const locked = await lockReservedOutpoints(wallet, body.excludeOutpoints ?? [], trusted);
+    const fund = () =>
+      coinSelection
+        ? createSelectedPsbt(coinSelection.selection)
+        : createPsbt(body.feeRate, undefined);
+    try {
+      return await fund();
+    } catch (e: any) {
+      const msg = String(e?.message ?? '').toLowerCase();
+      if (msg.includes('wallet is currently rescanning')) {
+        console.warn('[mintService] wallet rescanning detected, waiting', { wallet });
+        await waitForWalletRescan(wallet);
+        return await fund();
+      }
+      throw e;
+    } finally {
+      await unlockOutpoints(wallet, locked);
+    }
+  });
+  console.info('[mintService] funding psbt ready', {
     wallet,
     fee: psbtResult.fee,
     changePositions: psbtResult.changepositions
   });
 
-  const decoded = await runCliJson<DecodedPsbt>(['decodepsbt', psbtResult.psbt]);
+  let decoded = await runCliJson<DecodedPsbt>(['decodepsbt', psbtResult.psbt]);
   const inputs = decoded.tx.vin.map((input) => ({ txid: input.txid, vout: input.vout }));
+
+  // The package of this transaction and its unconfirmed ancestors must pay the
+  // requested rate, so the transaction tops up ancestors that pay less.
+  let fundingFeeRate = body.feeRate;
+  let unconfirmedAncestors: UnconfirmedAncestors | undefined;
+  if (body.spendUnconfirmed) {
+    unconfirmedAncestors = await collectUnconfirmedAncestors(inputs.map((input) => input.txid));
+    if (unconfirmedAncestors.count > body.spendUnconfirmed.maxAncestors) {
+      throw new Error(
+        `Unconfirmed ancestor count ${unconfirmedAncestors.count} exceeds limit ${body.spendUnconfirmed.maxAncestors}`
+      );
+    }
+    fundingFeeRate = childFeeRateForPackage(
+      body.feeRate,
+      decoded.tx.vsize ?? 0,
+      unconfirmedAncestors
+    );
+    if (fundingFeeRate > body.feeRate) {
+      // same inputs, so the ancestry is unchanged
+      if (coinSelection) {
+        const { selection, target } = coinSelection;
+        const bumped = finalizeSelection(selection.strategy, selection.inputs, {
+          ...target,
+          feeRate: fundingFeeRate
+        });
+        if (!bumped) {
+          throw new Error(`Selected inputs cannot pay the package fee rate ${fundingFeeRate} sat/vB`);
+        }
+        psbtResult = await createSelectedPsbt(bumped);
+      } else {
+        psbtResult = await createPsbt(fundingFeeRate, inputs);
+      }
+      decoded = await runCliJson<DecodedPsbt>(['decodepsbt', psbtResult.psbt]);
+    }
+    if (unconfirmedAncestors.count > 0) {
+      const feeSats = Math.round((decoded.fee ?? psbtResult.fee) * SATS_PER_BTC);
+      console.info('[mintService] spending unconfirmed inputs', {
+        wallet,
+        ...unconfirmedAncestors,
+        fundingFeeRate,
+        packageFeeRate: packageFeeRate(feeSats, decoded.tx.vsize ?? 0, unconfirmedAncestors)
+      });
+    }
+  }
   const changeOutput = findOutputByAddress(decoded.tx.vout, body.payment.address);
   console.info('[mintService] decodepsbt', {
     wallet,
@@ -407,20 +792,29 @@ export async function buildMintPsbt(body: MintRequestBody): Promise<MintPsbtResu
     hasChange: Boolean(changeOutput)
   });
 
-  const rawOutputs = buildOutputsObject(
+  const changeSats = changeOutput
+    ? splitChangeSats(Math.round(changeOutput.value * SATS_PER_BTC), fundingFeeRate, body.changeSplit)
+    : [];
+  if (changeSats.length > 1) {
+    console.info('[mintService] splitting change', { wallet, outputs: changeSats });
+  }
+  const rawOutputs = buildOutputsList(
     body.ordinals.address,
     feeRecipientAddr,
     vaultAddress,
     body.payment.address,
     resolvedAmounts,
-    changeOutput
+    changeSats
   );
 
   const rawTxInputs = inputs.map(({ txid, vout }) => ({ txid, vout }));
+  // replaceable=true signals BIP-125 so the canister can fee-bump a stuck mint
   const rawTx = await runCliRaw([
     'createrawtransaction',
     JSON.stringify(rawTxInputs),
-    JSON.stringify(rawOutputs)
+    JSON.stringify(rawOutputs),
+    '0',
+    'true'
   ]);
   console.info('[mintService] createrawtransaction', { wallet, rawTxLength: rawTx.length });
 
@@ -444,10 +838,13 @@ export async function buildMintPsbt(body: MintRequestBody): Promise<MintPsbtResu
     changeOutput: changeOutput
       ? { address: body.payment.address, amountBtc: changeOutput.value.toFixed(8) }
       : undefined,
+    changeOutputCount: changeSats.length,
+    unconfirmedAncestors,
     collateralSats: resolvedAmounts.vaultSats,
     rune: body.rune,
     feeRate: body.feeRate,
     ordinalsAddress: body.ordinals.address,
-    paymentAddress: body.payment.address
+    paymentAddress: body.payment.address,
+    feeSubsidySats
   };
 }
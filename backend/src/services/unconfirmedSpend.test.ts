@@ -0,0 +1,62 @@
+import assert from 'node:assert/strict';
+import { describe, it } from 'node:test';
+import {
+  childFeeRateForPackage,
+  isNotInMempool,
+  packageFeeRate,
+  untrustedUnconfirmed
+} from './unconfirmedSpend.js';
+
+const CANISTER_TXID = 'aa'.repeat(32);
+const FOREIGN_TXID = 'bb'.repeat(32);
+
+describe('untrustedUnconfirmed', () => {
+  it('keeps confirmed outputs and the canister change spendable', () => {
+    const unspent = [
+      { txid: CANISTER_TXID.toUpperCase(), vout: 4, confirmations: 0 },
+      { txid: FOREIGN_TXID, vout: 0, confirmations: 0 },
+      { txid: FOREIGN_TXID, vout: 1, confirmations: 3 }
+    ];
+    assert.deepEqual(untrustedUnconfirmed(unspent, new Set([CANISTER_TXID])), [
+      { txid: FOREIGN_TXID, vout: 0 }
+    ]);
+  });
+
+  it('excludes every unconfirmed output when the canister built nothing', () => {
+    const unspent = [{ txid: CANISTER_TXID, vout: 4, confirmations: 0 }];
+    assert.equal(untrustedUnconfirmed(unspent, new Set()).length, 1);
+  });
+});
+
+describe('childFeeRateForPackage', () => {
+  const ancestors = { count: 1, vsize: 200, feeSats: 200 };
+
+  it('tops up ancestors paying below the target', () => {
+    // 10 sat/vB over 400 vB needs 4000 sats; the ancestor paid 200
+    const rate = childFeeRateForPackage(10, 200, ancestors);
+    assert.equal(rate, 19);
+    assert.ok(packageFeeRate(rate * 200, 200, ancestors) >= 10);
+  });
+
+  it('never goes below the target for well-paying ancestors', () => {
+    assert.equal(childFeeRateForPackage(10, 200, { count: 1, vsize: 200, feeSats: 10_000 }), 10);
+  });
+
+  it('uses the target when nothing is unconfirmed', () => {
+    assert.equal(childFeeRateForPackage(10, 200, { count: 0, vsize: 0, feeSats: 0 }), 10);
+  });
+});
+
+describe('packageFeeRate', () => {
+  it('divides the package fee by the package size', () => {
+    assert.equal(packageFeeRate(2000, 200, { count: 1, vsize: 200, feeSats: 2000 }), 10);
+    assert.equal(packageFeeRate(0, 0, { count: 0, vsize: 0, feeSats: 0 }), 0);
+  });
+});
+
+describe('isNotInMempool', () => {
+  it('only matches the error for confirmed transactions', () => {
+    assert.ok(isNotInMempool(new Error('error code: -5\nTransaction not in mempool')));
+    assert.ok(!isNotInMempool(new Error('Could not connect to the server 127.0.0.1:8332')));
+  });
+});
@@ -0,0 +1,56 @@
+import type { Outpoint, UnconfirmedAncestors } from '../types.js';
+
+export interface UnspentOutput extends Outpoint {
+  confirmations: number;
+}
+
+/**
+ * Unconfirmed outputs that did not come from a transaction the canister built.
+ * Only the canister's own change may be spent before it confirms; anything
+ * else could be replaced or never confirm.
+ */
+export function untrustedUnconfirmed(
+  unspent: UnspentOutput[],
+  canisterTxids: Set<string>
+): Outpoint[] {
+  return unspent
+    .filter(({ txid, confirmations }) => confirmations === 0 && !canisterTxids.has(txid.toLowerCase()))
+    .map(({ txid, vout }) => ({ txid: txid.toLowerCase(), vout }));
+}
+
+/** Fee rate (sat/vB) of the new transaction together with its unconfirmed ancestors. */
+export function packageFeeRate(
+  feeSats: number,
+  vsize: number,
+  ancestors: UnconfirmedAncestors
+): number {
+  const packageVsize = vsize + ancestors.vsize;
+  return packageVsize > 0 ? (feeSats + ancestors.feeSats) / packageVsize : 0;
+}
+
+/**
+ * Fee rate the new transaction needs for the package to reach `targetFeeRate`.
+ * Ancestors paying less than the target are topped up by the child (CPFP);
+ * ancestors paying more never lower the child's rate below the target.
+ */
+export function childFeeRateForPackage(
+  targetFeeRate: number,
+  vsize: number,
+  ancestors: UnconfirmedAncestors
+): number {
+  if (vsize <= 0 || ancestors.count === 0) {
+    return targetFeeRate;
+  }
+  const shortfallSats = targetFeeRate * ancestors.vsize - ancestors.feeSats;
+  if (shortfallSats <= 0) {
+    return targetFeeRate;
+  }
+  // round up to the 0.001 sat/vB bitcoind accepts
+  return Math.ceil((targetFeeRate + shortfallSats / vsize) * 1000) / 1000;
+}
+
+/** getmempoolentry fails this way for inputs that are already confirmed. */
+export function isNotInMempool(error: unknown): boolean {
+  const message = String((error as { message?: unknown })?.message ?? error).toLowerCase();
+  return message.includes('not in mempool');
+}
@@ -9,8 +9,17 @@ interface BitcoinTxInfo {
   in_active_chain?: boolean;
 }
 
-function determineHealth(ratioBps?: number, withdrawable?: boolean): VaultHealthStatus {
-  if (ratioBps !== undefined && ratioBps < config.healthAtRiskRatioBps) {
+function determineHealth(
+  ratioBps?: number,
+  withdrawable?: boolean,
+  previous?: VaultHealthStatus
+): VaultHealthStatus {
+  // Hysteresis: an at-risk vault has to climb past the exit band to recover.
+  const threshold =
+    previous === 'at_risk'
+      ? Math.max(config.healthExitRatioBps, config.healthAtRiskRatioBps)
+      : config.healthAtRiskRatioBps;
+  if (ratioBps !== undefined && ratioBps < threshold) {
     return 'at_risk';
   }
   if (withdrawable) {
@@ -40,10 +49,22 @@ export async function refreshVaultHealth(record: VaultRecord): Promise<VaultReco
   const collateralBtc = record.collateralSats / SATS_PER_BTC;
   const collateralUsd = collateralBtc * price;
   const mintedUsd = record.metadata.mintUsdCents / 100;
-  const collateralRatioBps =
-    mintedUsd > 0 ? Math.round((collateralUsd / mintedUsd) * 10_000) : undefined;
+  // Only USD debt can be valued here; the canister values other quote assets.
+  const collateralRatioBps = record.metadata.quoteAsset
+    ? record.collateralRatioBps
+    : mintedUsd > 0
+      ? Math.round((collateralUsd / mintedUsd) * 10_000)
+      : undefined;
   const withdrawable = confirmations >= record.minConfirmations;
-  const health = determineHealth(collateralRatioBps, withdrawable);
+  const health = determineHealth(collateralRatioBps, withdrawable, record.health);
+  if (health !== record.health && (health === 'at_risk' || record.health === 'at_risk')) {
+    console.info('[vaultHealth] band transition', {
+      vaultId: record.vaultId,
+      from: record.health,
+      to: health,
+      collateralRatioBps
+    });
+  }
 
   const updated =
     (await vaultStore.updateVault(record.vaultId, {
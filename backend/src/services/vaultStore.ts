@@ -10,6 +10,8 @@ export interface VaultRecordMetadata {
   paymentAddress: string;
   mintTokens: number;
   mintUsdCents: number;
+  /** Fiat the debt is denominated in; absent for USD vaults. */
+  quoteAsset?: string;
 }
 
 export type VaultHealthStatus = 'pending' | 'confirmed' | 'at_risk';
@@ -89,7 +91,8 @@ class VaultStore {
       ordinalsAddress: legacyMeta.ordinalsAddress,
       paymentAddress: legacyMeta.paymentAddress,
       mintTokens: legacyMeta.mintTokens ?? 0,
-      mintUsdCents: legacyMeta.mintUsdCents ?? 0
+      mintUsdCents: legacyMeta.mintUsdCents ?? 0,
+      quoteAsset: legacyMeta.quoteAsset
     };
     return {
       ...record,
@@ -152,6 +155,21 @@ class VaultStore {
     console.info('[vaultStore] withdraw txid recorded', { vaultId, txid });
   }
 
+  /** Drops the owner's address linkage from a closed vault, keeping the amounts. */
+  async redactVault(vaultId: string): Promise<boolean> {
+    await this.ready;
+    const found = this.records.get(vaultId);
+    if (!found) return false;
+    this.records.set(vaultId, {
+      ...found,
+      descriptor: '',
+      metadata: { ...found.metadata, ordinalsAddress: '', paymentAddress: '' }
+    });
+    await this.persist();
+    console.info('[vaultStore] vault redacted', { vaultId });
+    return true;
+  }
+
   async updateVault(vaultId: string, patch: Partial<VaultRecord>): Promise<VaultRecord | undefined> {
     await this.ready;
     const found = this.records.get(vaultId);
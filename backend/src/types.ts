@@ -13,7 +13,43 @@ export interface MintRequestBody {
   vaultId: string;
   protocolPublicKey: string;
   protocolChainCode: string;
+  // legacy per-field overrides; entries in outputsOverride take precedence
   amounts?: Partial<MintOutputAmounts>;
+  outputsOverride?: OutputsOverride | null;
+  changeSplit?: ChangeSplitPolicy | null;
+  spendUnconfirmed?: UnconfirmedSpendPolicy | null;
+  excludeOutpoints?: Outpoint[] | null;
+  coinSelection?: CoinSelectionPolicy | null;
+  recoveryCsvBlocks?: number | null;
+  // protocol-pays fee mode: sats taken off the fee recipient output
+  feeSubsidySats?: number | null;
+}
+
+export type CoinSelectionStrategy = 'largest_first' | 'branch_and_bound' | 'single_random_draw';
+
+export interface CoinSelectionPolicy {
+  strategy: CoinSelectionStrategy;
+  dustThresholdSats?: number;
+}
+
+export interface Outpoint {
+  txid: string;
+  vout: number;
+}
+
+export interface UnconfirmedSpendPolicy {
+  maxAncestors: number;
+}
+
+export interface UnconfirmedAncestors {
+  count: number;
+  vsize: number;
+  feeSats: number;
+}
+
+export interface ChangeSplitPolicy {
+  maxOutputs: number;
+  targetOutputSats: number;
 }
 
 export interface MintOutputAmounts {
@@ -22,6 +58,14 @@ export interface MintOutputAmounts {
   vaultSats: number;
 }
 
+export type MintOutputRole = 'ordinals' | 'fee_recipient' | 'vault';
+
+/** Per-output amounts in whole sats, as sent by the canister. */
+export interface OutputsOverride {
+  version: 1;
+  outputs: Array<{ role: MintOutputRole; sats: number }>;
+}
+
 export interface MintPsbtResult {
   wallet: string;
   vaultAddress: string;
@@ -32,11 +76,14 @@ export interface MintPsbtResult {
   originalPsbt: string;
   patchedPsbt: string;
   rawTransactionHex: string;
-  inputs: Array<{ txid: string; vout: number }>;
+  inputs: Outpoint[];
   changeOutput?: { address: string; amountBtc: string };
+  changeOutputCount?: number;
+  unconfirmedAncestors?: UnconfirmedAncestors;
   collateralSats: number;
   rune: string;
   feeRate: number;
   ordinalsAddress: string;
   paymentAddress: string;
+  feeSubsidySats?: number;
 }
@@ -0,0 +1,77 @@
+import assert from 'node:assert/strict';
+import { EventEmitter } from 'node:events';
+import { describe, it } from 'node:test';
+import type { Request, Response } from 'express';
+import { idempotentRequest } from './idempotency.js';
+
+function postRequest(key: string | undefined, body: unknown, method = 'POST') {
+  return {
+    method,
+    body,
+    header: (name: string) => (name.toLowerCase() === 'idempotency-key' ? key : undefined)
+  } as unknown as Request;
+}
+
+class FakeResponse extends EventEmitter {
+  statusCode = 200;
+  payload: unknown;
+  headers: Record<string, string> = {};
+
+  status(code: number) {
+    this.statusCode = code;
+    return this;
+  }
+
+  json(payload: unknown) {
+    this.payload = payload;
+    setImmediate(() => this.emit('close'));
+    return this;
+  }
+
+  setHeader(name: string, value: string) {
+    this.headers[name] = value;
+  }
+}
+
+async function send(req: Request, handled: { count: number }, status = 201) {
+  const res = new FakeResponse();
+  await idempotentRequest(req, res as unknown as Response, () => {
+    handled.count += 1;
+    res.status(status).json({ run: handled.count });
+  });
+  await new Promise((resolve) => setImmediate(resolve));
+  return res;
+}
+
+describe('idempotentRequest', () => {
+  it('replays the stored response for a repeated key and body', async () => {
+    const handled = { count: 0 };
+    const req = () => postRequest('7:mint_finalize:replay', { vaultId: '7' });
+    const [first, second] = await Promise.all([send(req(), handled), send(req(), handled)]);
+    const third = await send(req(), handled);
+    assert.equal(handled.count, 1);
+    for (const res of [first, second, third]) {
+      assert.equal(res.statusCode, 201);
+      assert.deepEqual(res.payload, { run: 1 });
+    }
+    assert.equal(third.headers['idempotent-replay'], 'true');
+  });
+
+  it('runs the route again for another body, a failed attempt or no key', async () => {
+    const handled = { count: 0 };
+    await send(postRequest('7:withdraw_finalize:body', { vaultId: '7' }), handled);
+    const changed = await send(postRequest('7:withdraw_finalize:body', { vaultId: '8' }), handled);
+    assert.equal(changed.headers['idempotent-replay'], undefined);
+    assert.equal(handled.count, 2);
+
+    const failed = postRequest('7:mint_finalize:failed', { vaultId: '7' });
+    assert.equal((await send(failed, handled, 502)).statusCode, 502);
+    assert.equal((await send(failed, handled)).statusCode, 201);
+    assert.equal(handled.count, 4);
+
+    await send(postRequest(undefined, { vaultId: '7' }), handled);
+    await send(postRequest('7:mint_finalize:get', { vaultId: '7' }, 'GET'), handled);
+    await send(postRequest('7:mint_finalize:get', { vaultId: '7' }, 'GET'), handled);
+    assert.equal(handled.count, 7);
+  });
+});
@@ -0,0 +1,77 @@
+import { createHash } from 'node:crypto';
+import type { NextFunction, Request, Response } from 'express';
+
+const TTL_MS = 24 * 60 * 60 * 1000;
+
+interface CompletedEntry {
+  bodyHash: string;
+  status: number;
+  payload: unknown;
+  expiresAt: number;
+}
+
+interface InFlightEntry {
+  bodyHash: string;
+  done: Promise<void>;
+}
+
+const completed = new Map<string, CompletedEntry>();
+const inFlight = new Map<string, InFlightEntry>();
+
+function hashBody(body: unknown): string {
+  return createHash('sha256').update(JSON.stringify(body ?? null)).digest('hex');
+}
+
+function pruneExpired(now: number) {
+  for (const [key, entry] of completed) {
+    if (entry.expiresAt <= now) {
+      completed.delete(key);
+    }
+  }
+}
+
+/**
+ * Replays the stored response for a repeated `Idempotency-Key` with the same body.
+ * Concurrent duplicates (one per subnet replica) wait for the first request to
+ * finish. Only final 200/201 responses are stored, so a 202 signature prompt or
+ * an error lets the caller retry with the same key.
+ */
+export async function idempotentRequest(req: Request, res: Response, next: NextFunction) {
+  const key = req.header('idempotency-key');
+  if (!key || req.method !== 'POST') {
+    return next();
+  }
+  const bodyHash = hashBody(req.body);
+  const now = Date.now();
+  pruneExpired(now);
+
+  const pending = inFlight.get(key);
+  if (pending && pending.bodyHash === bodyHash) {
+    await pending.done;
+  }
+  const cached = completed.get(key);
+  if (cached && cached.bodyHash === bodyHash) {
+    res.setHeader('idempotent-replay', 'true');
+    return res.status(cached.status).json(cached.payload);
+  }
+
+  let finish: () => void = () => {};
+  const done = new Promise<void>((resolve) => {
+    finish = resolve;
+  });
+  inFlight.set(key, { bodyHash, done });
+  const originalJson = res.json.bind(res);
+  res.json = (payload: unknown) => {
+    if (res.statusCode === 200 || res.statusCode === 201) {
+      completed.set(key, { bodyHash, status: res.statusCode, payload, expiresAt: now + TTL_MS });
+    }
+    return originalJson(payload);
+  };
+  res.on('close', () => {
+    if (inFlight.get(key)?.done === done) {
+      inFlight.delete(key);
+    }
+    finish();
+  });
+  next();
+}
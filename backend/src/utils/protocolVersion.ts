@@ -0,0 +1,24 @@
+import type { NextFunction, Request, Response } from 'express';
+
+/** Newest version of the JSON contract served to the canister. */
+export const PROTOCOL_VERSION = 1;
+/** Oldest version still served; raise it only once no canister speaks it. */
+export const MIN_PROTOCOL_VERSION = 1;
+
+export function protocolVersionInfo() {
+  return { protocolVersion: PROTOCOL_VERSION, minProtocolVersion: MIN_PROTOCOL_VERSION };
+}
+
+/**
+ * Checks the `x-protocol-version` the canister sends with every call. Canisters
+ * built before the handshake send none and speak version 1.
+ */
+export function requireProtocolVersion(req: Request, res: Response, next: NextFunction) {
+  const header = req.header('x-protocol-version');
+  const version = header === undefined ? 1 : Number(header);
+  if (!Number.isInteger(version) || version < MIN_PROTOCOL_VERSION || version > PROTOCOL_VERSION) {
+    return res.status(426).json({ error: 'PROTOCOL_VERSION_UNSUPPORTED', ...protocolVersionInfo() });
+  }
+  res.setHeader('x-protocol-version', String(version));
+  next();
+}
@@ -0,0 +1,96 @@
+import assert from 'node:assert/strict';
+import { EventEmitter } from 'node:events';
+import { describe, it } from 'node:test';
+import type { Response } from 'express';
+import { config } from '../config.js';
+import { canisterSignature, requireCanisterSignature, type RawBodyRequest } from './requestSignature.js';
+
+const SECRET = 's'.repeat(32);
+
+function signedRequest(method: string, path: string, body: string, nonce: string, signedPath = path) {
+  const timestamp = String(Math.floor(Date.now() / 1000));
+  const rawBody = Buffer.from(body);
+  const signature = canisterSignature(SECRET, timestamp, nonce, method, signedPath, rawBody);
+  const headers: Record<string, string> = {
+    'x-timestamp': timestamp,
+    'x-nonce': nonce,
+    'x-signature': signature.toString('hex')
+  };
+  return {
+    method,
+    originalUrl: path,
+    rawBody,
+    header: (name: string) => headers[name.toLowerCase()]
+  } as unknown as RawBodyRequest;
+}
+
+class FakeResponse extends EventEmitter {
+  statusCode = 200;
+  payload: unknown;
+  headers: Record<string, string> = {};
+
+  status(code: number) {
+    this.statusCode = code;
+    return this;
+  }
+
+  json(payload: unknown) {
+    this.payload = payload;
+    setImmediate(() => this.emit('close'));
+    return this;
+  }
+
+  setHeader(name: string, value: string) {
+    this.headers[name] = value;
+  }
+}
+
+async function send(req: RawBodyRequest, handled: { count: number }) {
+  const res = new FakeResponse();
+  await requireCanisterSignature(req, res as unknown as Response, () => {
+    handled.count += 1;
+    res.status(201).json({ vaultId: handled.count });
+  });
+  await new Promise((resolve) => setImmediate(resolve));
+  return res;
+}
+
+describe('requireCanisterSignature', () => {
+  config.hmacSecret = SECRET;
+
+  it('runs a replayed request once and answers repeats with the first response', async () => {
+    const handled = { count: 0 };
+    const req = () => signedRequest('POST', '/mint/finalize', '{"vault":1}', 'nonce-replay');
+    const [first, second] = await Promise.all([send(req(), handled), send(req(), handled)]);
+    const third = await send(req(), handled);
+    assert.equal(handled.count, 1);
+    for (const res of [first, second, third]) {
+      assert.equal(res.statusCode, 201);
+      assert.deepEqual(res.payload, { vaultId: 1 });
+    }
+    assert.equal(third.headers['x-nonce-replay'], 'true');
+  });
+
+  it('refuses a signature sent to another route or method', async () => {
+    const handled = { count: 0 };
+    const moved = await send(
+      signedRequest('POST', '/withdraw/finalize', '{"vault":1}', 'nonce-route', '/mint/finalize'),
+      handled
+    );
+    assert.equal(moved.statusCode, 401);
+    assert.deepEqual(moved.payload, { error: 'SIGNATURE_INVALID' });
+
+    const req = signedRequest('POST', '/mint/finalize', '', 'nonce-method');
+    const asGet = { ...req, method: 'GET', header: req.header } as RawBodyRequest;
+    assert.equal((await send(asGet, handled)).statusCode, 401);
+    assert.equal(handled.count, 0);
+  });
+
+  it('refuses a nonce reused under a different signature', async () => {
+    const handled = { count: 0 };
+    await send(signedRequest('POST', '/mint/build', '{"a":1}', 'nonce-reused'), handled);
+    const reused = await send(signedRequest('POST', '/mint/build', '{"a":2}', 'nonce-reused'), handled);
+    assert.equal(reused.statusCode, 409);
+    assert.equal(handled.count, 1);
+  });
+});
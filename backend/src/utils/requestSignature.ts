@@ -0,0 +1,109 @@
+import { createHmac, timingSafeEqual } from 'node:crypto';
+import type { NextFunction, Request, Response } from 'express';
+import { config } from '../config.js';
+
+export type RawBodyRequest = Request & { rawBody?: Buffer };
+
+interface SeenNonce {
+  signature: string;
+  expiresAt: number;
+  done: Promise<void>;
+  response?: { status: number; payload: unknown };
+}
+
+// Nonces of signed requests still inside the skew window.
+const seenNonces = new Map<string, SeenNonce>();
+
+function pruneExpired(now: number) {
+  for (const [nonce, entry] of seenNonces) {
+    if (entry.expiresAt <= now) {
+      seenNonces.delete(nonce);
+    }
+  }
+}
+
+/** HMAC-SHA256 over `${timestamp}.${nonce}.${method}.${path}.${body}`. */
+export function canisterSignature(
+  secret: string,
+  timestamp: string,
+  nonce: string,
+  method: string,
+  path: string,
+  body: Buffer
+): Buffer {
+  return createHmac('sha256', secret)
+    .update(`${timestamp}.${nonce}.${method.toUpperCase()}.${path}.`)
+    .update(body)
+    .digest();
+}
+
+/**
+ * Verifies the HMAC-SHA256 signature the canister attaches to outgoing calls.
+ * The signed message binds the method and path (with query) to the body, so a
+ * signature only authorizes the route it was made for.
+ *
+ * A nonce runs its route once. Every subnet replica sends an identical
+ * request, so repeats with the same signature wait for the first one and get
+ * its response back rather than running the route again; nonces are
+ * remembered until their timestamp leaves the skew window.
+ */
+export async function requireCanisterSignature(
+  req: RawBodyRequest,
+  res: Response,
+  next: NextFunction
+) {
+  if (!config.hmacSecret) {
+    return next();
+  }
+  const timestamp = req.header('x-timestamp');
+  const nonce = req.header('x-nonce');
+  const signature = req.header('x-signature');
+  if (!timestamp || !nonce || !signature) {
+    return res.status(401).json({ error: 'SIGNATURE_MISSING' });
+  }
+  const now = Date.now();
+  const skew = Math.abs(now / 1000 - Number(timestamp));
+  if (!Number.isFinite(skew) || skew > config.hmacMaxSkewSeconds) {
+    return res.status(401).json({ error: 'SIGNATURE_EXPIRED' });
+  }
+  const expected = canisterSignature(
+    config.hmacSecret,
+    timestamp,
+    nonce,
+    req.method,
+    req.originalUrl,
+    req.rawBody ?? Buffer.alloc(0)
+  );
+  const provided = Buffer.from(signature, 'hex');
+  if (provided.length !== expected.length || !timingSafeEqual(provided, expected)) {
+    return res.status(401).json({ error: 'SIGNATURE_INVALID' });
+  }
+
+  pruneExpired(now);
+  const seen = seenNonces.get(nonce);
+  if (seen) {
+    await seen.done;
+    if (seen.signature !== signature || !seen.response) {
+      return res.status(409).json({ error: 'NONCE_REUSED' });
+    }
+    res.setHeader('x-nonce-replay', 'true');
+    return res.status(seen.response.status).json(seen.response.payload);
+  }
+
+  let finish: () => void = () => {};
+  const entry: SeenNonce = {
+    signature,
+    expiresAt: (Number(timestamp) + config.hmacMaxSkewSeconds) * 1000,
+    done: new Promise<void>((resolve) => {
+      finish = resolve;
+    })
+  };
+  seenNonces.set(nonce, entry);
+  const originalJson = res.json.bind(res);
+  res.json = (payload: unknown) => {
+    entry.response = { status: res.statusCode, payload };
+    return originalJson(payload);
+  };
+  res.on('close', finish);
+  next();
+}
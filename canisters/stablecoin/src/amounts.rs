@@ -0,0 +1,162 @@
+// Fixed-point money arithmetic. Prices are whole units of 1e-8 USD per BTC
+// and amounts are whole sats or USD cents, so collateral requirements, vault
+// values and redemption payouts round the same way on every replica. Floats
+// only appear where a price is shown to a user or crosses the candid
+// interface.
+
+const SATS_PER_BTC: u128 = 100_000_000;
+/// Price units per USD.
+const PRICE_SCALE: u128 = 100_000_000;
+const CENTS_PER_USD: u128 = 100;
+const BPS_PER_UNIT: u128 = 10_000;
+/// `sats * price` units per USD cent.
+const SAT_PRICE_PER_CENT: u128 = SATS_PER_BTC * PRICE_SCALE / CENTS_PER_USD;
+
+/// BTC/USD price in units of 1e-8 USD per BTC; never zero.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct BtcPrice(u128);
+
+impl BtcPrice {
+    pub const fn from_e8(units: u128) -> Option<Self> {
+        if units == 0 {
+            None
+        } else {
+            Some(Self(units))
+        }
+    }
+
+    /// XRC rate carrying `decimals` fractional digits; digits beyond 1e-8 USD
+    /// are dropped.
+    pub fn from_rate(rate: u64, decimals: u32) -> Option<Self> {
+        let units = if decimals <= 8 {
+            u128::from(rate).checked_mul(10u128.checked_pow(8 - decimals)?)?
+        } else {
+            u128::from(rate) / 10u128.checked_pow(decimals - 8)?
+        };
+        Self::from_e8(units)
+    }
+
+    /// Price given in USD (dev-mode and test mocks), rounded to 1e-8 USD.
+    pub fn from_usd(usd: f64) -> Option<Self> {
+        let units = (usd * PRICE_SCALE as f64).round();
+        if !units.is_finite() || units < 1.0 || units >= u128::MAX as f64 {
+            return None;
+        }
+        Self::from_e8(units as u128)
+    }
+
+    /// Raw units, for persisting the price.
+    pub const fn e8(self) -> u128 {
+        self.0
+    }
+
+    /// Distance from `reference` in basis points of `reference`, rounded down
+    /// and capped at `u32::MAX`.
+    pub fn deviation_bps(self, reference: BtcPrice) -> u32 {
+        let ratio = self.0.abs_diff(reference.0).saturating_mul(BPS_PER_UNIT) / reference.0;
+        u32::try_from(ratio).unwrap_or(u32::MAX)
+    }
+
+    /// For display and candid fields only.
+    pub fn to_usd(self) -> f64 {
+        self.0 as f64 / PRICE_SCALE as f64
+    }
+
+    /// Sats worth at least `ratio_bps` of `usd_cents`, rounded up.
+    pub fn collateral_sats(self, ratio_bps: u16, usd_cents: u64) -> u64 {
+        let scaled =
+            u128::from(usd_cents) * u128::from(ratio_bps) * (SAT_PRICE_PER_CENT / BPS_PER_UNIT);
+        saturate(scaled.div_ceil(self.0))
+    }
+
+    /// Sats worth at most `usd_cents`, rounded down.
+    pub fn sats_for_usd_cents(self, usd_cents: u64) -> u64 {
+        saturate(u128::from(usd_cents) * SAT_PRICE_PER_CENT / self.0)
+    }
+
+    /// USD cents `sats` are worth, rounded up.
+    pub fn usd_cents_for_sats(self, sats: u64) -> u64 {
+        saturate(
+            u128::from(sats)
+                .saturating_mul(self.0)
+                .div_ceil(SAT_PRICE_PER_CENT),
+        )
+    }
+
+    /// This price times `bps` basis points, rounded down; None if that is zero.
+    pub fn scaled_bps(self, bps: u16) -> Option<Self> {
+        Self::from_e8(self.0 * u128::from(bps) / BPS_PER_UNIT)
+    }
+
+    /// Collateral ratio of `sats` against `debt_usd_cents` in basis points,
+    /// rounded down and capped at `u32::MAX`.
+    pub fn collateral_ratio_bps(self, sats: u64, debt_usd_cents: u64) -> u32 {
+        let Some(scaled) = u128::from(sats)
+            .checked_mul(self.0)
+            .and_then(|value| value.checked_mul(BPS_PER_UNIT))
+        else {
+            return u32::MAX;
+        };
+        let ratio = scaled / (u128::from(debt_usd_cents).max(1) * SAT_PRICE_PER_CENT);
+        u32::try_from(ratio).unwrap_or(u32::MAX)
+    }
+}
+
+fn saturate(value: u128) -> u64 {
+    u64::try_from(value).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xrc_rates_convert_exactly() {
+        let price = BtcPrice::from_rate(60_123_456_789_012, 9).unwrap();
+        assert_eq!(price, BtcPrice::from_e8(6_012_345_678_901).unwrap());
+        assert_eq!(
+            BtcPrice::from_rate(6_012_345, 2),
+            BtcPrice::from_e8(6_012_345_000_000)
+        );
+        assert_eq!(BtcPrice::from_rate(9, 9), None);
+        assert_eq!(BtcPrice::from_usd(100_734.10).unwrap().to_usd(), 100_734.10);
+        assert_eq!(BtcPrice::from_usd(-1.0), None);
+        assert_eq!(BtcPrice::from_usd(f64::NAN), None);
+    }
+
+    #[test]
+    fn collateral_rounds_against_the_user() {
+        // $20 at 150% and $30,000/BTC is exactly 100,000 sats.
+        let price = BtcPrice::from_usd(30_000.0).unwrap();
+        assert_eq!(price.collateral_sats(15_000, 2_000), 100_000);
+        assert_eq!(price.sats_for_usd_cents(2_000), 66_666);
+        // A price one unit higher still rounds the requirement up to whole sats.
+        let price = BtcPrice::from_e8(3_000_000_000_001).unwrap();
+        assert_eq!(price.collateral_sats(15_000, 2_000), 100_000);
+        assert_eq!(price.collateral_ratio_bps(100_000, 2_000), 15_000);
+        assert_eq!(price.collateral_ratio_bps(99_999, 2_000), 14_999);
+        assert_eq!(price.collateral_ratio_bps(u64::MAX, 1), u32::MAX);
+    }
+
+    #[test]
+    fn deviation_is_relative_to_the_reference() {
+        let reference = BtcPrice::from_usd(50_000.0).unwrap();
+        assert_eq!(
+            BtcPrice::from_usd(60_000.0)
+                .unwrap()
+                .deviation_bps(reference),
+            2_000
+        );
+        assert_eq!(
+            BtcPrice::from_usd(40_000.0)
+                .unwrap()
+                .deviation_bps(reference),
+            2_000
+        );
+        assert_eq!(
+            reference.deviation_bps(BtcPrice::from_usd(40_000.0).unwrap()),
+            2_500
+        );
+        assert_eq!(reference.deviation_bps(reference), 0);
+    }
+}
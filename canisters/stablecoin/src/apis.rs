@@ -0,0 +1,375 @@
+// Seams between the vault logic and the system canisters it calls: the
+// Bitcoin API and threshold Schnorr on the management canister, the
+// BTC/USD price oracle, the ICRC ledgers of ckBTC and the stablecoin, and the
+// runes indexer that proves debt burns. Canister entry points pass the production
+// implementations; unit tests pass the mocks so quoting, key derivation,
+// signing and balance checks run natively instead of only on a replica.
+
+use ic_cdk::api::call::CallResult;
+use ic_cdk::api::management_canister::bitcoin::{
+    bitcoin_get_balance, bitcoin_get_utxos, bitcoin_send_transaction, GetBalanceRequest,
+    GetUtxosRequest, GetUtxosResponse, SendTransactionRequest,
+};
+
+use candid::Principal;
+
+use crate::icrc::{self, Account};
+use crate::logging::log;
+use crate::tx::OutPoint;
+use crate::{BtcPrice, SignWithSchnorrAux, StablecoinError};
+
+pub(crate) trait BitcoinApi {
+    async fn get_utxos(&self, request: GetUtxosRequest) -> CallResult<(GetUtxosResponse,)>;
+
+    async fn get_balance(&self, request: GetBalanceRequest) -> CallResult<(u64,)>;
+
+    async fn send_transaction(&self, request: SendTransactionRequest) -> CallResult<()>;
+}
+
+pub(crate) trait SchnorrApi {
+    /// x-only public key and chain code for `derivation_path`.
+    async fn public_key(
+        &self,
+        key_name: &str,
+        derivation_path: Vec<Vec<u8>>,
+    ) -> Result<([u8; 32], Vec<u8>), StablecoinError>;
+
+    /// 64-byte BIP-340 signature over `message`.
+    async fn sign(
+        &self,
+        key_name: &str,
+        derivation_path: Vec<Vec<u8>>,
+        message: [u8; 32],
+        aux: Option<SignWithSchnorrAux>,
+    ) -> Result<Vec<u8>, StablecoinError>;
+}
+
+pub(crate) trait OracleApi {
+    /// BTC price in the fiat `quote_asset`, used to size and value collateral.
+    async fn btc_price(&self, quote_asset: &str) -> Result<BtcPrice, StablecoinError>;
+}
+
+pub(crate) trait LedgerApi {
+    /// Moves `amount` out of one of this canister's subaccounts; returns the block index.
+    async fn transfer(
+        &self,
+        from_subaccount: Option<[u8; 32]>,
+        to: Account,
+        amount: u64,
+    ) -> Result<u64, StablecoinError>;
+
+    /// Moves `amount` under an allowance `from` granted this canister.
+    async fn transfer_from(
+        &self,
+        from: Account,
+        to: Account,
+        amount: u64,
+    ) -> Result<u64, StablecoinError>;
+
+    async fn fee(&self) -> Result<u64, StablecoinError>;
+}
+
+pub(crate) trait RuneIndexApi {
+    /// Units of the burn rune held by `outpoint`; 0 when it holds none.
+    async fn rune_balance(&self, outpoint: &OutPoint) -> Result<u128, StablecoinError>;
+}
+
+/// The management canister's Bitcoin API and threshold Schnorr.
+pub(crate) struct ManagementCanister;
+
+impl BitcoinApi for ManagementCanister {
+    /// Answered from fixtures in test builds.
+    async fn get_utxos(&self, request: GetUtxosRequest) -> CallResult<(GetUtxosResponse,)> {
+        #[cfg(feature = "test-support")]
+        if let Some(response) = crate::test_support::utxo_fixture(&request.address) {
+            return response;
+        }
+        bitcoin_get_utxos(request).await
+    }
+
+    /// Served from UTXO fixtures in test builds.
+    async fn get_balance(&self, request: GetBalanceRequest) -> CallResult<(u64,)> {
+        #[cfg(feature = "test-support")]
+        if let Some(response) =
+            crate::test_support::balance_fixture(&request.address, request.min_confirmations)
+        {
+            return response;
+        }
+        bitcoin_get_balance(request).await
+    }
+
+    /// Captured in test builds and skipped in offline dev mode.
+    async fn send_transaction(&self, request: SendTransactionRequest) -> CallResult<()> {
+        #[cfg(feature = "test-support")]
+        if let Some(result) = crate::test_support::capture_transaction(&request.transaction) {
+            return result;
+        }
+        if crate::offline_dev_mode() {
+            log!(
+                Debug,
+                "dev_mode",
+                "offline, not sending {} byte transaction",
+                request.transaction.len()
+            );
+            return Ok(());
+        }
+        bitcoin_send_transaction(request).await
+    }
+}
+
+impl SchnorrApi for ManagementCanister {
+    async fn public_key(
+        &self,
+        key_name: &str,
+        derivation_path: Vec<Vec<u8>>,
+    ) -> Result<([u8; 32], Vec<u8>), StablecoinError> {
+        crate::schnorr_x_only_public_key(key_name, derivation_path).await
+    }
+
+    async fn sign(
+        &self,
+        key_name: &str,
+        derivation_path: Vec<Vec<u8>>,
+        message: [u8; 32],
+        aux: Option<SignWithSchnorrAux>,
+    ) -> Result<Vec<u8>, StablecoinError> {
+        crate::sign_with_schnorr_aux(key_name, derivation_path, message, aux).await
+    }
+}
+
+/// XRC, or the dev-mode / test-support mock price when one is set.
+pub(crate) struct ExchangeRateCanister;
+
+impl OracleApi for ExchangeRateCanister {
+    async fn btc_price(&self, quote_asset: &str) -> Result<BtcPrice, StablecoinError> {
+        crate::quote_btc_price(quote_asset).await
+    }
+}
+
+/// An ICRC-1/ICRC-2 ledger canister.
+pub(crate) struct IcrcLedger(pub Principal);
+
+impl LedgerApi for IcrcLedger {
+    async fn transfer(
+        &self,
+        from_subaccount: Option<[u8; 32]>,
+        to: Account,
+        amount: u64,
+    ) -> Result<u64, StablecoinError> {
+        icrc::transfer(self.0, from_subaccount, to, amount).await
+    }
+
+    async fn transfer_from(
+        &self,
+        from: Account,
+        to: Account,
+        amount: u64,
+    ) -> Result<u64, StablecoinError> {
+        icrc::transfer_from(self.0, from, to, amount).await
+    }
+
+    async fn fee(&self) -> Result<u64, StablecoinError> {
+        icrc::fee(self.0).await
+    }
+}
+
+/// An ord server's `/output` endpoint, reached over HTTPS outcalls.
+pub(crate) struct OrdIndexer {
+    pub base_url: String,
+    /// spaced rune name, as ord reports it
+    pub rune_name: String,
+}
+
+impl RuneIndexApi for OrdIndexer {
+    async fn rune_balance(&self, outpoint: &OutPoint) -> Result<u128, StablecoinError> {
+        crate::ord_rune_balance(&self.base_url, &self.rune_name, outpoint).await
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod mock {
+    use std::cell::{Cell, RefCell};
+    use std::collections::BTreeMap;
+    use std::future::Future;
+    use std::pin::pin;
+    use std::task::{Context, Poll, Waker};
+
+    use ic_cdk::api::call::RejectionCode;
+    use ic_cdk::api::management_canister::bitcoin::UtxoFilter;
+    use k256::schnorr::SigningKey;
+
+    use super::*;
+    use crate::tx;
+
+    /// Drives a future whose mocks never suspend.
+    pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+        let mut context = Context::from_waker(Waker::noop());
+        match pin!(future).poll(&mut context) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("mock calls complete synchronously"),
+        }
+    }
+
+    /// Serves UTXOs per address, later pages by their token, and accepts
+    /// every transaction.
+    #[derive(Default)]
+    pub(crate) struct MockBitcoin {
+        pub utxos: BTreeMap<String, GetUtxosResponse>,
+        pub pages: BTreeMap<Vec<u8>, GetUtxosResponse>,
+        pub utxo_calls: Cell<u32>,
+    }
+
+    impl BitcoinApi for MockBitcoin {
+        async fn get_utxos(&self, request: GetUtxosRequest) -> CallResult<(GetUtxosResponse,)> {
+            self.utxo_calls.set(self.utxo_calls.get() + 1);
+            match &request.filter {
+                Some(UtxoFilter::Page(token)) => self.pages.get(token),
+                _ => self.utxos.get(&request.address),
+            }
+            .cloned()
+            .map(|response| (response,))
+            .ok_or((RejectionCode::CanisterReject, "unknown address".to_string()))
+        }
+
+        /// Sums the address's UTXOs with at least `min_confirmations`.
+        async fn get_balance(&self, request: GetBalanceRequest) -> CallResult<(u64,)> {
+            let (response,) = self
+                .get_utxos(GetUtxosRequest {
+                    address: request.address,
+                    network: request.network,
+                    filter: None,
+                })
+                .await?;
+            let min = request.min_confirmations.unwrap_or(0);
+            Ok((response
+                .utxos
+                .iter()
+                .filter(|utxo| {
+                    min == 0
+                        || (utxo.height > 0
+                            && response.tip_height.saturating_sub(utxo.height) + 1 >= min)
+                })
+                .map(|utxo| utxo.value)
+                .sum(),))
+        }
+
+        async fn send_transaction(&self, _request: SendTransactionRequest) -> CallResult<()> {
+            Ok(())
+        }
+    }
+
+    /// Real BIP-340 keys derived from the key name and path, so signatures
+    /// verify; the BIP-341 tweak requested through `aux` is not applied.
+    #[derive(Default)]
+    pub(crate) struct MockSchnorr {
+        pub signed: RefCell<Vec<[u8; 32]>>,
+    }
+
+    impl MockSchnorr {
+        fn signing_key(key_name: &str, derivation_path: &[Vec<u8>]) -> SigningKey {
+            let mut seed = key_name.as_bytes().to_vec();
+            for segment in derivation_path {
+                seed.extend_from_slice(&(segment.len() as u32).to_be_bytes());
+                seed.extend_from_slice(segment);
+            }
+            SigningKey::from_bytes(&tx::sha256(&seed)).expect("hash is a valid scalar")
+        }
+    }
+
+    impl SchnorrApi for MockSchnorr {
+        async fn public_key(
+            &self,
+            key_name: &str,
+            derivation_path: Vec<Vec<u8>>,
+        ) -> Result<([u8; 32], Vec<u8>), StablecoinError> {
+            let key = Self::signing_key(key_name, &derivation_path);
+            Ok((key.verifying_key().to_bytes().into(), vec![0u8; 32]))
+        }
+
+        async fn sign(
+            &self,
+            key_name: &str,
+            derivation_path: Vec<Vec<u8>>,
+            message: [u8; 32],
+            _aux: Option<SignWithSchnorrAux>,
+        ) -> Result<Vec<u8>, StablecoinError> {
+            let key = Self::signing_key(key_name, &derivation_path);
+            let signature = key
+                .sign_prehash_with_aux_rand(&message, &[0u8; 32])
+                .map_err(|err| StablecoinError::SigningError(err.to_string()))?;
+            self.signed.borrow_mut().push(message);
+            Ok(signature.to_bytes().to_vec())
+        }
+    }
+
+    /// Fixed price, or a failing oracle when None.
+    pub(crate) struct MockOracle(pub Option<BtcPrice>);
+
+    impl OracleApi for MockOracle {
+        async fn btc_price(&self, _quote_asset: &str) -> Result<BtcPrice, StablecoinError> {
+            self.0
+                .ok_or_else(|| StablecoinError::XrcError("mock oracle unavailable".into()))
+        }
+    }
+
+    /// (from subaccount, to, amount)
+    type Transfer = (Option<[u8; 32]>, Account, u64);
+
+    /// Records transfers in call order; `transfer` fails once `transfers_fail`
+    /// is set.
+    #[derive(Default)]
+    pub(crate) struct MockLedger {
+        pub fee: u64,
+        pub transfers_fail: bool,
+        pub transfers: RefCell<Vec<Transfer>>,
+        /// `transfer_from` calls: (from, to, amount)
+        pub pulls: RefCell<Vec<(Account, Account, u64)>>,
+    }
+
+    impl LedgerApi for MockLedger {
+        async fn transfer(
+            &self,
+            from_subaccount: Option<[u8; 32]>,
+            to: Account,
+            amount: u64,
+        ) -> Result<u64, StablecoinError> {
+            if self.transfers_fail {
+                return Err(StablecoinError::LedgerError("mock transfer failed".into()));
+            }
+            let mut transfers = self.transfers.borrow_mut();
+            transfers.push((from_subaccount, to, amount));
+            Ok(transfers.len() as u64)
+        }
+
+        async fn transfer_from(
+            &self,
+            from: Account,
+            to: Account,
+            amount: u64,
+        ) -> Result<u64, StablecoinError> {
+            let mut pulls = self.pulls.borrow_mut();
+            pulls.push((from, to, amount));
+            Ok(pulls.len() as u64)
+        }
+
+        async fn fee(&self) -> Result<u64, StablecoinError> {
+            Ok(self.fee)
+        }
+    }
+
+    /// Rune balances per (txid, vout); unknown outpoints hold none.
+    #[derive(Default)]
+    pub(crate) struct MockRuneIndex {
+        pub balances: BTreeMap<([u8; 32], u32), u128>,
+    }
+
+    impl RuneIndexApi for MockRuneIndex {
+        async fn rune_balance(&self, outpoint: &OutPoint) -> Result<u128, StablecoinError> {
+            Ok(self
+                .balances
+                .get(&(outpoint.txid, outpoint.vout))
+                .copied()
+                .unwrap_or(0))
+        }
+    }
+}
@@ -0,0 +1,138 @@
+// BIP-322 "simple" message signatures for taproot addresses. The signature is
+// the witness of a virtual transaction spending an output that commits to the
+// message, so it proves control of the address without an on-chain spend and
+// can never authorize a real one.
+
+use crate::taproot::{self, SIGHASH_DEFAULT};
+use crate::tx::{self, OutPoint, Transaction, TxIn, TxOut};
+use crate::{invalid_input, StablecoinError};
+
+const MESSAGE_TAG: &str = "BIP0322-signed-message";
+
+pub(crate) fn message_hash(message: &[u8]) -> [u8; 32] {
+    taproot::tagged_hash(MESSAGE_TAG, message)
+}
+
+/// The virtual transaction whose only output `to_sign` spends.
+pub(crate) fn to_spend(script_pubkey: &[u8], message: &[u8]) -> Transaction {
+    let mut script_sig = vec![0x00, 0x20];
+    script_sig.extend_from_slice(&message_hash(message));
+    Transaction {
+        version: 0,
+        inputs: vec![TxIn {
+            previous_output: OutPoint {
+                txid: [0u8; 32],
+                vout: 0xffff_ffff,
+            },
+            script_sig,
+            sequence: 0,
+            witness: Vec::new(),
+        }],
+        outputs: vec![TxOut {
+            value: 0,
+            script_pubkey: script_pubkey.to_vec(),
+        }],
+        lock_time: 0,
+    }
+}
+
+fn to_sign(to_spend: &Transaction) -> Transaction {
+    Transaction {
+        version: 0,
+        inputs: vec![TxIn {
+            previous_output: OutPoint {
+                txid: to_spend.txid(),
+                vout: 0,
+            },
+            script_sig: Vec::new(),
+            sequence: 0,
+            witness: Vec::new(),
+        }],
+        outputs: vec![TxOut {
+            value: 0,
+            script_pubkey: vec![crate::script::OP_RETURN],
+        }],
+        lock_time: 0,
+    }
+}
+
+/// Key-path sighash a proof for `script_pubkey` over `message` signs.
+pub(crate) fn sighash(script_pubkey: &[u8], message: &[u8]) -> Result<[u8; 32], StablecoinError> {
+    let to_spend = to_spend(script_pubkey, message);
+    taproot::taproot_sighash(
+        &to_sign(&to_spend),
+        &to_spend.outputs,
+        0,
+        None,
+        SIGHASH_DEFAULT,
+    )
+}
+
+/// Base64 witness stack of a key-path proof.
+pub(crate) fn encode_simple(signature: &[u8]) -> String {
+    let mut witness = Vec::new();
+    tx::write_compact_size(&mut witness, 1);
+    tx::write_var_bytes(&mut witness, signature);
+    tx::base64_encode(&witness)
+}
+
+/// Checks a simple proof against a P2TR `script_pubkey`. Only key-path
+/// witnesses are supported.
+pub(crate) fn verify_simple(
+    script_pubkey: &[u8],
+    message: &[u8],
+    proof: &str,
+) -> Result<bool, StablecoinError> {
+    let output_key = match script_pubkey {
+        [0x51, 0x20, key @ ..] => crate::to_array_32(key)?,
+        _ => return Err(invalid_input("only taproot addresses are supported")),
+    };
+    let witness = tx::base64_decode(proof)?;
+    let mut reader = tx::Reader::new(&witness);
+    if reader.read_compact_size()? != 1 {
+        return Err(invalid_input("expected a key-path witness"));
+    }
+    let signature = reader.read_var_bytes()?;
+    if !reader.is_empty() {
+        return Err(invalid_input("trailing witness data"));
+    }
+    // sighash types other than the default are not used by proofs
+    if signature.len() != 64 {
+        return Err(invalid_input("expected a 64-byte signature"));
+    }
+    let sighash = sighash(script_pubkey, message)?;
+    crate::verify_bip340_signature(&output_key, &sighash, &signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_bip322_vectors() {
+        assert_eq!(
+            crate::to_hex(&message_hash(b"Hello World")),
+            "f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a"
+        );
+        // to_spend of bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l, empty message
+        let script = crate::from_hex("00142b05d564e6a7a33c087f16e0f730d1440123799d").unwrap();
+        assert_eq!(
+            to_spend(&script, b"").txid_hex(),
+            "c5680aa69bb8d860bf82d4e9cd3504b55dde018de765a91bb566283c545a99a7"
+        );
+    }
+
+    #[test]
+    fn key_path_proofs_round_trip() {
+        use k256::schnorr::SigningKey;
+
+        let key = SigningKey::from_bytes(&[7u8; 32]).unwrap();
+        let mut script = vec![0x51, 0x20];
+        script.extend_from_slice(&key.verifying_key().to_bytes());
+        let digest = sighash(&script, b"challenge").unwrap();
+        let signature = key.sign_prehash_with_aux_rand(&digest, &[0u8; 32]).unwrap();
+        let proof = encode_simple(&signature.to_bytes());
+        assert!(verify_simple(&script, b"challenge", &proof).unwrap());
+        assert!(!verify_simple(&script, b"other", &proof).unwrap());
+    }
+}
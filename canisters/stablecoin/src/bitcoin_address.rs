@@ -0,0 +1,443 @@
+// Bitcoin address encoding. Decodes bech32 (segwit v0), bech32m (v1+) and
+// base58check P2PKH/P2SH addresses into the scriptPubKey they pay to and the
+// network they belong to, and encodes scriptPubKeys back into addresses.
+// Addresses supplied by callers go through `validate` at the API boundary, so
+// a mistyped address or one for another network is rejected there instead of
+// deep inside the backend or on-chain.
+
+use ic_cdk::api::management_canister::bitcoin::BitcoinNetwork;
+
+use crate::tx::sha256d;
+use crate::{invalid_input, StablecoinError};
+
+const BECH32_CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const BECH32_CONST: u32 = 1;
+const BECH32M_CONST: u32 = 0x2bc8_30a3;
+const BASE58_ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// Network an address was encoded for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum AddressNetwork {
+    Mainnet,
+    Testnet,
+    Regtest,
+    /// base58 version bytes are shared by testnet and regtest
+    TestnetOrRegtest,
+}
+
+impl AddressNetwork {
+    pub fn accepts(self, network: BitcoinNetwork) -> bool {
+        matches!(
+            (self, network),
+            (Self::Mainnet, BitcoinNetwork::Mainnet)
+                | (
+                    Self::Testnet | Self::TestnetOrRegtest,
+                    BitcoinNetwork::Testnet
+                )
+                | (
+                    Self::Regtest | Self::TestnetOrRegtest,
+                    BitcoinNetwork::Regtest
+                )
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct BitcoinAddress {
+    pub network: AddressNetwork,
+    pub script_pubkey: Vec<u8>,
+}
+
+/// Decodes a segwit address (`bc`, `tb` or `bcrt`) or a base58check P2PKH/P2SH
+/// address of mainnet or testnet/regtest.
+pub(crate) fn decode(address: &str) -> Result<BitcoinAddress, StablecoinError> {
+    let address = address.trim();
+    if let Some(decoded) = decode_segwit(address)? {
+        return Ok(decoded);
+    }
+    let payload = base58check_decode(address)?;
+    if payload.len() != 21 {
+        return Err(invalid_input("invalid_address_length"));
+    }
+    let network = match payload[0] {
+        0x00 | 0x05 => AddressNetwork::Mainnet,
+        0x6f | 0xc4 => AddressNetwork::TestnetOrRegtest,
+        _ => return Err(invalid_input("unsupported_address_version")),
+    };
+    let hash = &payload[1..];
+    let mut script = Vec::with_capacity(25);
+    if matches!(payload[0], 0x00 | 0x6f) {
+        script.extend_from_slice(&[0x76, 0xa9, 0x14]);
+        script.extend_from_slice(hash);
+        script.extend_from_slice(&[0x88, 0xac]);
+    } else {
+        script.extend_from_slice(&[0xa9, 0x14]);
+        script.extend_from_slice(hash);
+        script.push(0x87);
+    }
+    Ok(BitcoinAddress {
+        network,
+        script_pubkey: script,
+    })
+}
+
+/// scriptPubKey an address pays to, on whichever network it was encoded for.
+pub(crate) fn script_pubkey(address: &str) -> Result<Vec<u8>, StablecoinError> {
+    decode(address).map(|decoded| decoded.script_pubkey)
+}
+
+/// Decodes the caller-supplied `address` named `field` and checks it belongs
+/// to `network`; any network is accepted while none is configured.
+pub(crate) fn validate(
+    field: &str,
+    address: &str,
+    network: Option<BitcoinNetwork>,
+) -> Result<BitcoinAddress, StablecoinError> {
+    let decoded = decode(address).map_err(|err| match err {
+        StablecoinError::InvalidInput(reason) => invalid_input(format!("{}: {}", field, reason)),
+        other => other,
+    })?;
+    if let Some(network) = network {
+        if !decoded.network.accepts(network) {
+            return Err(invalid_input(format!(
+                "{}: address_network_mismatch (expected {:?})",
+                field, network
+            )));
+        }
+    }
+    Ok(decoded)
+}
+
+fn bech32_polymod(values: impl Iterator<Item = u8>) -> u32 {
+    const GEN: [u32; 5] = [
+        0x3b6a_57b2,
+        0x2650_8e6d,
+        0x1ea1_19fa,
+        0x3d42_33dd,
+        0x2a14_62b3,
+    ];
+    let mut chk = 1u32;
+    for value in values {
+        let top = chk >> 25;
+        chk = ((chk & 0x01ff_ffff) << 5) ^ value as u32;
+        for (i, gen) in GEN.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= gen;
+            }
+        }
+    }
+    chk
+}
+
+/// Decodes a segwit address, or returns `None` if it has no known bech32 prefix.
+fn decode_segwit(address: &str) -> Result<Option<BitcoinAddress>, StablecoinError> {
+    let lower = address.to_ascii_lowercase();
+    let Some(sep) = lower.rfind('1') else {
+        return Ok(None);
+    };
+    let (hrp, data) = (&lower[..sep], &lower[sep + 1..]);
+    let network = match hrp {
+        "bc" => AddressNetwork::Mainnet,
+        "tb" => AddressNetwork::Testnet,
+        "bcrt" => AddressNetwork::Regtest,
+        _ => return Ok(None),
+    };
+    let has_lower = address.bytes().any(|b| b.is_ascii_lowercase());
+    let has_upper = address.bytes().any(|b| b.is_ascii_uppercase());
+    if has_lower && has_upper {
+        return Err(invalid_input("mixed_case_address"));
+    }
+    if data.len() < 7 || lower.len() > 90 {
+        return Err(invalid_input("invalid_address_length"));
+    }
+    let values = data
+        .bytes()
+        .map(|c| {
+            BECH32_CHARSET
+                .iter()
+                .position(|&x| x == c)
+                .map(|pos| pos as u8)
+                .ok_or_else(|| invalid_input("invalid_bech32_character"))
+        })
+        .collect::<Result<Vec<u8>, _>>()?;
+    let expanded = hrp
+        .bytes()
+        .map(|b| b >> 5)
+        .chain(std::iter::once(0))
+        .chain(hrp.bytes().map(|b| b & 0x1f))
+        .chain(values.iter().copied());
+    let checksum = bech32_polymod(expanded);
+    let version = values[0];
+    let expected = if version == 0 {
+        BECH32_CONST
+    } else {
+        BECH32M_CONST
+    };
+    if version > 16 || checksum != expected {
+        return Err(invalid_input("invalid_address_checksum"));
+    }
+    let mut program = Vec::new();
+    let mut acc = 0u32;
+    let mut bits = 0u32;
+    for &value in &values[1..values.len() - 6] {
+        acc = (acc << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            program.push((acc >> bits) as u8);
+        }
+    }
+    if bits >= 5 || (acc & ((1 << bits) - 1)) != 0 {
+        return Err(invalid_input("invalid_witness_program_padding"));
+    }
+    let valid_len = match version {
+        0 => program.len() == 20 || program.len() == 32,
+        _ => (2..=40).contains(&program.len()),
+    };
+    if !valid_len {
+        return Err(invalid_input("invalid_witness_program_length"));
+    }
+    let mut script = Vec::with_capacity(program.len() + 2);
+    script.push(if version == 0 { 0x00 } else { 0x50 + version });
+    script.push(program.len() as u8);
+    script.extend_from_slice(&program);
+    Ok(Some(BitcoinAddress {
+        network,
+        script_pubkey: script,
+    }))
+}
+
+fn base58check_decode(address: &str) -> Result<Vec<u8>, StablecoinError> {
+    let mut bytes: Vec<u8> = Vec::new();
+    for c in address.bytes() {
+        let mut carry = BASE58_ALPHABET
+            .iter()
+            .position(|&x| x == c)
+            .ok_or_else(|| invalid_input("invalid_base58_character"))?
+            as u32;
+        for byte in bytes.iter_mut().rev() {
+            carry += (*byte as u32) * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.insert(0, carry as u8);
+            carry >>= 8;
+        }
+    }
+    let leading_zeros = address.bytes().take_while(|&c| c == b'1').count();
+    let mut decoded = vec![0u8; leading_zeros];
+    decoded.extend_from_slice(&bytes);
+    if decoded.len() < 4 {
+        return Err(invalid_input("invalid_address_length"));
+    }
+    let (payload, checksum) = decoded.split_at(decoded.len() - 4);
+    if sha256d(payload)[..4] != *checksum {
+        return Err(invalid_input("invalid_address_checksum"));
+    }
+    Ok(payload.to_vec())
+}
+
+fn base58check_encode(version: u8, hash: &[u8]) -> String {
+    let mut payload = vec![version];
+    payload.extend_from_slice(hash);
+    let checksum = sha256d(&payload);
+    payload.extend_from_slice(&checksum[..4]);
+    // base-58 digits, least significant first
+    let mut digits: Vec<u8> = Vec::new();
+    for &byte in &payload {
+        let mut carry = u32::from(byte);
+        for digit in digits.iter_mut() {
+            carry += u32::from(*digit) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    let leading_zeros = payload.iter().take_while(|&&b| b == 0).count();
+    std::iter::repeat_n(b'1', leading_zeros)
+        .chain(digits.iter().rev().map(|&d| BASE58_ALPHABET[d as usize]))
+        .map(char::from)
+        .collect()
+}
+
+/// bech32m address of the P2TR output for `output_key` under `hrp`
+/// (`bc`, `tb` or `bcrt`).
+pub(crate) fn p2tr_address(hrp: &str, output_key: &[u8; 32]) -> String {
+    segwit_address(hrp, 1, output_key)
+}
+
+/// Address `script_pubkey` pays to on `network`, or None for scripts without
+/// an address form (OP_RETURN, bare multisig, non-standard).
+pub(crate) fn from_script_pubkey(script_pubkey: &[u8], network: BitcoinNetwork) -> Option<String> {
+    let mainnet = matches!(network, BitcoinNetwork::Mainnet);
+    match script_pubkey {
+        [0x76, 0xa9, 0x14, hash @ .., 0x88, 0xac] if hash.len() == 20 => {
+            Some(base58check_encode(if mainnet { 0x00 } else { 0x6f }, hash))
+        }
+        [0xa9, 0x14, hash @ .., 0x87] if hash.len() == 20 => {
+            Some(base58check_encode(if mainnet { 0x05 } else { 0xc4 }, hash))
+        }
+        [version @ (0x00 | 0x51..=0x60), len, program @ ..]
+            if usize::from(*len) == program.len()
+                && match version {
+                    0x00 => program.len() == 20 || program.len() == 32,
+                    _ => (2..=40).contains(&program.len()),
+                } =>
+        {
+            let hrp = match network {
+                BitcoinNetwork::Mainnet => "bc",
+                BitcoinNetwork::Testnet => "tb",
+                BitcoinNetwork::Regtest => "bcrt",
+            };
+            let version = if *version == 0x00 { 0 } else { version - 0x50 };
+            Some(segwit_address(hrp, version, program))
+        }
+        _ => None,
+    }
+}
+
+fn segwit_address(hrp: &str, version: u8, program: &[u8]) -> String {
+    let mut values = vec![version];
+    let mut acc = 0u32;
+    let mut bits = 0u32;
+    for &byte in program {
+        acc = ((acc << 8) | byte as u32) & 0xfff;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            values.push(((acc >> bits) & 0x1f) as u8);
+        }
+    }
+    if bits > 0 {
+        values.push(((acc << (5 - bits)) & 0x1f) as u8);
+    }
+    let expanded = hrp
+        .bytes()
+        .map(|b| b >> 5)
+        .chain(std::iter::once(0))
+        .chain(hrp.bytes().map(|b| b & 0x1f))
+        .chain(values.iter().copied())
+        .chain([0u8; 6]);
+    let constant = if version == 0 {
+        BECH32_CONST
+    } else {
+        BECH32M_CONST
+    };
+    let checksum = bech32_polymod(expanded) ^ constant;
+    values.extend((0..6).map(|i| ((checksum >> (5 * (5 - i))) & 0x1f) as u8));
+    let mut address = format!("{}1", hrp);
+    address.extend(values.iter().map(|&v| BECH32_CHARSET[v as usize] as char));
+    address
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::to_hex;
+    use crate::tx::p2tr_script_pubkey;
+
+    #[test]
+    fn decodes_addresses() {
+        assert_eq!(
+            to_hex(&script_pubkey("BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4").unwrap()),
+            "0014751e76e8199196d454941c45d1b3a323f1433bd6"
+        );
+        assert_eq!(
+            to_hex(
+                &script_pubkey("bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0")
+                    .unwrap()
+            ),
+            "512079be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"
+        );
+        assert_eq!(
+            to_hex(&script_pubkey("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2").unwrap()),
+            "76a91477bff20c60e522dfaa3350c39b030a5d004e839a88ac"
+        );
+        // bech32 checksum on a v1 program must be rejected (bech32m required)
+        assert!(
+            script_pubkey("bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj1")
+                .is_err()
+        );
+        assert!(script_pubkey("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN3").is_err());
+    }
+
+    #[test]
+    fn encodes_p2tr_addresses() {
+        let key: [u8; 32] =
+            crate::from_hex("79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")
+                .unwrap()
+                .try_into()
+                .unwrap();
+        assert_eq!(
+            p2tr_address("bc", &key),
+            "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0"
+        );
+        let regtest = p2tr_address("bcrt", &[9u8; 32]);
+        assert_eq!(
+            script_pubkey(&regtest).unwrap(),
+            p2tr_script_pubkey(&[9u8; 32])
+        );
+    }
+
+    #[test]
+    fn encodes_script_pubkeys_as_addresses() {
+        for (address, network) in [
+            (
+                "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4",
+                BitcoinNetwork::Mainnet,
+            ),
+            (
+                "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0",
+                BitcoinNetwork::Mainnet,
+            ),
+            (
+                "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2",
+                BitcoinNetwork::Mainnet,
+            ),
+            (
+                "mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn",
+                BitcoinNetwork::Regtest,
+            ),
+        ] {
+            let script = script_pubkey(address).unwrap();
+            assert_eq!(
+                from_script_pubkey(&script, network).as_deref(),
+                Some(address)
+            );
+        }
+        assert_eq!(
+            from_script_pubkey(&[0x6a, 0x01, 0x00], BitcoinNetwork::Mainnet),
+            None
+        );
+    }
+
+    #[test]
+    fn rejects_addresses_of_other_networks() {
+        let mainnet = "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq";
+        assert!(validate("payment.address", mainnet, Some(BitcoinNetwork::Mainnet)).is_ok());
+        assert!(validate("payment.address", mainnet, None).is_ok());
+        assert!(matches!(
+            validate("payment.address", mainnet, Some(BitcoinNetwork::Testnet)),
+            Err(StablecoinError::InvalidInput(reason))
+                if reason == "payment.address: address_network_mismatch (expected Testnet)"
+        ));
+        // testnet base58 prefixes are valid on regtest too, bech32 ones are not
+        let legacy_testnet = "mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn";
+        assert!(validate(
+            "fee_recipient",
+            legacy_testnet,
+            Some(BitcoinNetwork::Regtest)
+        )
+        .is_ok());
+        let regtest = p2tr_address("bcrt", &[9u8; 32]);
+        assert!(validate("ordinals.address", &regtest, Some(BitcoinNetwork::Testnet)).is_err());
+        assert!(matches!(
+            validate("ordinals.address", "tb1qnotanaddress", None),
+            Err(StablecoinError::InvalidInput(reason)) if reason.starts_with("ordinals.address: ")
+        ));
+    }
+}
@@ -0,0 +1,463 @@
+// Certified vault data. The canister's certified data is the root of an IC
+// hash tree with two labeled branches:
+//
+//   "state_hash" -> leaf: the state hash also returned by `get_state_hash`
+//   "vaults"     -> one labeled leaf per vault, keyed by its id as 8
+//                   big-endian bytes, holding the SHA-256 of the vault's
+//                   candid-encoded `StoredVaultRecord`
+//
+// Query responses carry a witness: the same tree with every branch except
+// the requested one pruned to its digest. A client recomputes the witness
+// root, checks it against the `certified_data` in the IC certificate, and
+// compares the revealed leaf with the hash of the record it received.
+
+use std::collections::BTreeMap;
+
+use crate::tx::sha256;
+
+const STATE_HASH_LABEL: &[u8] = b"state_hash";
+const VAULTS_LABEL: &[u8] = b"vaults";
+/// CBOR self-describe tag the IC expects in front of an encoded hash tree.
+const CBOR_SELF_DESCRIBE_TAG: u64 = 55_799;
+
+/// IC hash tree as defined by the interface specification.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum HashTree {
+    Empty,
+    Fork(Box<HashTree>, Box<HashTree>),
+    Labeled(Vec<u8>, Box<HashTree>),
+    Leaf(Vec<u8>),
+    Pruned([u8; 32]),
+}
+
+impl HashTree {
+    fn fork(left: HashTree, right: HashTree) -> Self {
+        Self::Fork(Box::new(left), Box::new(right))
+    }
+
+    fn labeled(label: &[u8], tree: HashTree) -> Self {
+        Self::Labeled(label.to_vec(), Box::new(tree))
+    }
+
+    pub fn digest(&self) -> [u8; 32] {
+        match self {
+            Self::Empty => domain_hash("ic-hashtree-empty", &[]),
+            Self::Fork(left, right) => {
+                domain_hash("ic-hashtree-fork", &[&left.digest(), &right.digest()])
+            }
+            Self::Labeled(label, tree) => {
+                domain_hash("ic-hashtree-labeled", &[label, &tree.digest()])
+            }
+            Self::Leaf(value) => domain_hash("ic-hashtree-leaf", &[value]),
+            Self::Pruned(digest) => *digest,
+        }
+    }
+
+    /// Self-describing CBOR encoding, as agents decode witnesses.
+    pub fn to_cbor(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        cbor_header(&mut out, 6, CBOR_SELF_DESCRIBE_TAG);
+        self.write_cbor(&mut out);
+        out
+    }
+
+    fn write_cbor(&self, out: &mut Vec<u8>) {
+        match self {
+            Self::Empty => {
+                cbor_header(out, 4, 1);
+                cbor_header(out, 0, 0);
+            }
+            Self::Fork(left, right) => {
+                cbor_header(out, 4, 3);
+                cbor_header(out, 0, 1);
+                left.write_cbor(out);
+                right.write_cbor(out);
+            }
+            Self::Labeled(label, tree) => {
+                cbor_header(out, 4, 3);
+                cbor_header(out, 0, 2);
+                cbor_bytes(out, label);
+                tree.write_cbor(out);
+            }
+            Self::Leaf(value) => {
+                cbor_header(out, 4, 2);
+                cbor_header(out, 0, 3);
+                cbor_bytes(out, value);
+            }
+            Self::Pruned(digest) => {
+                cbor_header(out, 4, 2);
+                cbor_header(out, 0, 4);
+                cbor_bytes(out, digest);
+            }
+        }
+    }
+}
+
+fn domain_hash(domain: &str, parts: &[&[u8]]) -> [u8; 32] {
+    let mut data = Vec::with_capacity(1 + domain.len() + 64);
+    data.push(domain.len() as u8);
+    data.extend_from_slice(domain.as_bytes());
+    for part in parts {
+        data.extend_from_slice(part);
+    }
+    sha256(&data)
+}
+
+fn cbor_header(out: &mut Vec<u8>, major: u8, value: u64) {
+    let major = major << 5;
+    match value {
+        0..=23 => out.push(major | value as u8),
+        24..=0xff => out.extend_from_slice(&[major | 24, value as u8]),
+        0x100..=0xffff => {
+            out.push(major | 25);
+            out.extend_from_slice(&(value as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(major | 26);
+            out.extend_from_slice(&(value as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(major | 27);
+            out.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+}
+
+fn cbor_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    cbor_header(out, 2, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+/// Leaf hash of every stored vault, kept in step with `VAULTS`.
+#[derive(Default)]
+pub(crate) struct CertifiedVaults {
+    root: Option<Box<VaultNode>>,
+}
+
+impl CertifiedVaults {
+    /// Sets the leaf of `vault_id`, rehashing only the forks above it.
+    pub fn insert(&mut self, vault_id: u64, record_hash: [u8; 32]) {
+        self.root = Some(match self.root.take() {
+            None => VaultNode::leaf(vault_id, record_hash),
+            Some(root) => root.insert(vault_id, record_hash),
+        });
+    }
+
+    /// Root to publish as certified data alongside `state_hash`.
+    pub fn root_hash(&self, state_hash: &[u8; 32]) -> [u8; 32] {
+        HashTree::fork(state_hash_branch(state_hash), self.vaults_branch()).digest()
+    }
+
+    /// Witness revealing the state hash leaf only.
+    pub fn state_hash_witness(&self, state_hash: &[u8; 32]) -> HashTree {
+        HashTree::fork(
+            state_hash_branch(state_hash),
+            HashTree::Pruned(self.vaults_branch().digest()),
+        )
+    }
+
+    /// Witness revealing the leaf of `vault_id`, or None if it is not certified.
+    pub fn vault_witness(&self, state_hash: &[u8; 32], vault_id: u64) -> Option<HashTree> {
+        let root = self.root.as_ref()?;
+        Some(HashTree::fork(
+            HashTree::Pruned(state_hash_branch(state_hash).digest()),
+            HashTree::labeled(VAULTS_LABEL, root.witness(vault_id)?),
+        ))
+    }
+
+    /// The `vaults` branch with the vault tree pruned to its cached digest.
+    fn vaults_branch(&self) -> HashTree {
+        let vaults = match &self.root {
+            None => HashTree::Empty,
+            Some(root) => HashTree::Pruned(root.digest()),
+        };
+        HashTree::labeled(VAULTS_LABEL, vaults)
+    }
+}
+
+fn state_hash_branch(state_hash: &[u8; 32]) -> HashTree {
+    HashTree::labeled(STATE_HASH_LABEL, HashTree::Leaf(state_hash.to_vec()))
+}
+
+/// Crit-bit tree over vault ids: a fork splits its ids on the highest bit
+/// where they differ, so the shape depends only on the set of ids and an
+/// in-order walk visits them in label order. Each node keeps its digest.
+enum VaultNode {
+    Leaf {
+        vault_id: u64,
+        record_hash: [u8; 32],
+        digest: [u8; 32],
+    },
+    Fork {
+        /// bit the ids below split on; they agree on every higher bit
+        bit: u32,
+        left: Box<VaultNode>,
+        right: Box<VaultNode>,
+        digest: [u8; 32],
+    },
+}
+
+impl VaultNode {
+    fn leaf(vault_id: u64, record_hash: [u8; 32]) -> Box<Self> {
+        Box::new(Self::Leaf {
+            vault_id,
+            record_hash,
+            digest: vault_leaf(vault_id, &record_hash).digest(),
+        })
+    }
+
+    fn fork(bit: u32, left: Box<Self>, right: Box<Self>) -> Box<Self> {
+        let digest = domain_hash("ic-hashtree-fork", &[&left.digest(), &right.digest()]);
+        Box::new(Self::Fork {
+            bit,
+            left,
+            right,
+            digest,
+        })
+    }
+
+    fn digest(&self) -> [u8; 32] {
+        match self {
+            Self::Leaf { digest, .. } | Self::Fork { digest, .. } => *digest,
+        }
+    }
+
+    /// Some id of the subtree; all of them agree above a fork's bit.
+    fn any_id(&self) -> u64 {
+        match self {
+            Self::Leaf { vault_id, .. } => *vault_id,
+            Self::Fork { left, .. } => left.any_id(),
+        }
+    }
+
+    fn insert(self: Box<Self>, vault_id: u64, record_hash: [u8; 32]) -> Box<Self> {
+        let differing = self.any_id() ^ vault_id;
+        // highest bit where `vault_id` leaves this subtree's common prefix
+        let crit = 63u32.saturating_sub(differing.leading_zeros());
+        let inside = match *self {
+            Self::Leaf { .. } => differing == 0,
+            Self::Fork { bit, .. } => differing == 0 || crit <= bit,
+        };
+        if !inside {
+            let leaf = Self::leaf(vault_id, record_hash);
+            return if vault_id >> crit & 1 == 0 {
+                Self::fork(crit, leaf, self)
+            } else {
+                Self::fork(crit, self, leaf)
+            };
+        }
+        match *self {
+            Self::Leaf { .. } => Self::leaf(vault_id, record_hash),
+            Self::Fork {
+                bit, left, right, ..
+            } => {
+                if vault_id >> bit & 1 == 0 {
+                    Self::fork(bit, left.insert(vault_id, record_hash), right)
+                } else {
+                    Self::fork(bit, left, right.insert(vault_id, record_hash))
+                }
+            }
+        }
+    }
+
+    /// This subtree with every branch off the path to `vault_id` pruned, or
+    /// None when it does not hold `vault_id`.
+    fn witness(&self, vault_id: u64) -> Option<HashTree> {
+        match self {
+            Self::Leaf {
+                vault_id: id,
+                record_hash,
+                ..
+            } => (*id == vault_id).then(|| vault_leaf(vault_id, record_hash)),
+            Self::Fork {
+                bit, left, right, ..
+            } => {
+                if vault_id >> bit & 1 == 0 {
+                    Some(HashTree::fork(
+                        left.witness(vault_id)?,
+                        HashTree::Pruned(right.digest()),
+                    ))
+                } else {
+                    Some(HashTree::fork(
+                        HashTree::Pruned(left.digest()),
+                        right.witness(vault_id)?,
+                    ))
+                }
+            }
+        }
+    }
+}
+
+fn vault_leaf(vault_id: u64, record_hash: &[u8; 32]) -> HashTree {
+    HashTree::labeled(
+        &vault_id.to_be_bytes(),
+        HashTree::Leaf(record_hash.to_vec()),
+    )
+}
+
+/// Digest over keyed state leaves that changing one leaf updates in constant
+/// time: the leaf count and the sum mod 2^256 of `SHA-256(key || leaf)`
+/// over every leaf.
+#[derive(Default)]
+pub(crate) struct StateDigest {
+    leaves: BTreeMap<Vec<u8>, [u8; 32]>,
+    sum: [u8; 32],
+}
+
+impl StateDigest {
+    /// Sets the leaf under `key`; None removes it.
+    pub fn set(&mut self, key: Vec<u8>, leaf: Option<[u8; 32]>) {
+        let previous = match leaf {
+            Some(leaf) => self.leaves.insert(key.clone(), leaf),
+            None => self.leaves.remove(&key),
+        };
+        if let Some(previous) = previous {
+            sub_256(&mut self.sum, &leaf_term(&key, &previous));
+        }
+        if let Some(leaf) = leaf {
+            add_256(&mut self.sum, &leaf_term(&key, &leaf));
+        }
+    }
+
+    pub fn digest(&self) -> [u8; 32] {
+        domain_hash(
+            "usdb-state-v2",
+            &[&self.sum, &(self.leaves.len() as u64).to_be_bytes()],
+        )
+    }
+}
+
+fn leaf_term(key: &[u8], leaf: &[u8; 32]) -> [u8; 32] {
+    let mut data = Vec::with_capacity(8 + key.len() + 32);
+    data.extend_from_slice(&(key.len() as u64).to_be_bytes());
+    data.extend_from_slice(key);
+    data.extend_from_slice(leaf);
+    sha256(&data)
+}
+
+/// Big-endian `sum += term` mod 2^256.
+fn add_256(sum: &mut [u8; 32], term: &[u8; 32]) {
+    let mut carry = 0u16;
+    for (byte, add) in sum.iter_mut().zip(term).rev() {
+        let total = u16::from(*byte) + u16::from(*add) + carry;
+        *byte = total as u8;
+        carry = total >> 8;
+    }
+}
+
+/// Big-endian `sum -= term` mod 2^256.
+fn sub_256(sum: &mut [u8; 32], term: &[u8; 32]) {
+    let mut borrow = 0i16;
+    for (byte, sub) in sum.iter_mut().zip(term).rev() {
+        let total = i16::from(*byte) - i16::from(*sub) - borrow;
+        *byte = total.rem_euclid(256) as u8;
+        borrow = i16::from(total < 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn witness_reveals_one_vault_under_the_certified_root() {
+        let mut certified = CertifiedVaults::default();
+        for vault_id in 1..=5u64 {
+            certified.insert(vault_id, sha256(&vault_id.to_le_bytes()));
+        }
+        let state_hash = [7u8; 32];
+        let root = certified.root_hash(&state_hash);
+        let witness = certified.vault_witness(&state_hash, 4).unwrap();
+        assert_eq!(witness.digest(), root);
+        assert_eq!(certified.state_hash_witness(&state_hash).digest(), root);
+        assert!(certified.vault_witness(&state_hash, 9).is_none());
+
+        // only vault 4's leaf is revealed
+        fn leaves(tree: &HashTree, out: &mut Vec<Vec<u8>>) {
+            match tree {
+                HashTree::Fork(left, right) => {
+                    leaves(left, out);
+                    leaves(right, out);
+                }
+                HashTree::Labeled(_, tree) => leaves(tree, out),
+                HashTree::Leaf(value) => out.push(value.clone()),
+                HashTree::Empty | HashTree::Pruned(_) => {}
+            }
+        }
+        let mut revealed = Vec::new();
+        leaves(&witness, &mut revealed);
+        assert_eq!(revealed, vec![sha256(&4u64.to_le_bytes()).to_vec()]);
+
+        assert!(certified.vault_witness(&state_hash, 0).is_none());
+
+        assert_eq!(
+            HashTree::labeled(b"a", HashTree::Leaf(vec![1])).to_cbor(),
+            vec![0xd9, 0xd9, 0xf7, 0x83, 0x02, 0x41, b'a', 0x82, 0x03, 0x41, 0x01]
+        );
+    }
+
+    #[test]
+    fn vault_tree_shape_depends_only_on_its_leaves() {
+        let ids = [9u64, 1, 300, 2, 7, 1 << 40, 3, 64];
+        let mut forward = CertifiedVaults::default();
+        for id in ids {
+            forward.insert(id, [0; 32]);
+        }
+        let mut backward = CertifiedVaults::default();
+        for id in ids.iter().rev() {
+            backward.insert(*id, sha256(&id.to_le_bytes()));
+        }
+        // rewriting leaves in place lands on the same root
+        for id in ids {
+            forward.insert(id, sha256(&id.to_le_bytes()));
+        }
+        let state_hash = [3u8; 32];
+        assert_eq!(
+            forward.root_hash(&state_hash),
+            backward.root_hash(&state_hash)
+        );
+        for id in ids {
+            let witness = forward.vault_witness(&state_hash, id).unwrap();
+            assert_eq!(witness.digest(), forward.root_hash(&state_hash));
+        }
+
+        // leaves sit in increasing id order, as label lookups expect
+        fn walk(node: &VaultNode, out: &mut Vec<u64>) {
+            match node {
+                VaultNode::Leaf { vault_id, .. } => out.push(*vault_id),
+                VaultNode::Fork { left, right, .. } => {
+                    walk(left, out);
+                    walk(right, out);
+                }
+            }
+        }
+        let mut walked = Vec::new();
+        walk(forward.root.as_ref().unwrap(), &mut walked);
+        let mut sorted = ids.to_vec();
+        sorted.sort();
+        assert_eq!(walked, sorted);
+    }
+
+    #[test]
+    fn state_digest_tracks_leaves_in_any_order() {
+        let mut digest = StateDigest::default();
+        let empty = digest.digest();
+        digest.set(b"a".to_vec(), Some([1; 32]));
+        digest.set(b"b".to_vec(), Some([2; 32]));
+        let both = digest.digest();
+        digest.set(b"a".to_vec(), Some([9; 32]));
+        assert_ne!(digest.digest(), both);
+        digest.set(b"a".to_vec(), Some([1; 32]));
+        assert_eq!(digest.digest(), both);
+
+        let mut reversed = StateDigest::default();
+        reversed.set(b"b".to_vec(), Some([2; 32]));
+        reversed.set(b"a".to_vec(), Some([1; 32]));
+        assert_eq!(reversed.digest(), both);
+
+        digest.set(b"a".to_vec(), None);
+        digest.set(b"b".to_vec(), None);
+        assert_eq!(digest.digest(), empty);
+        assert_eq!(digest.sum, [0; 32]);
+    }
+}
@@ -0,0 +1,161 @@
+// Derivation paths under the threshold Schnorr keys. Every path starts with
+// the protocol domain followed by a role; protocol keys also carry a scheme
+// version, so a future layout change derives fresh keys for new vaults instead
+// of silently re-keying existing ones. Retired schemes stay here so the keys
+// registered under them remain derivable.
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+pub(crate) const DOMAIN_LABEL: &[u8] = b"usdb";
+pub(crate) const PROTOCOL_ROLE_LABEL: &[u8] = b"proto";
+pub(crate) const SELF_TEST_ROLE_LABEL: &[u8] = b"selftest";
+pub(crate) const GUARDIAN_ROLE_LABEL: &[u8] = b"guardian";
+pub(crate) const ROTATED_GUARDIAN_ROLE_LABEL: &[u8] = b"guardian-gen";
+pub(crate) const PROTOCOL_CHANGE_ROLE_LABEL: &[u8] = b"change";
+pub(crate) const COSIGNING_ROLE_LABEL: &[u8] = b"cosign";
+
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, CandidType, Deserialize, Serialize,
+)]
+pub(crate) enum DerivationScheme {
+    /// `usdb/proto/<vault_id>`, used before schemes were versioned
+    V0,
+    /// `usdb/v1/proto/<vault_id>`
+    V1,
+}
+
+impl DerivationScheme {
+    /// Scheme new protocol keys are derived under.
+    pub const CURRENT: DerivationScheme = DerivationScheme::V1;
+    pub const ALL: [DerivationScheme; 2] = [DerivationScheme::V0, DerivationScheme::V1];
+
+    /// Version segment; None for the unversioned legacy layout.
+    fn version_label(self) -> Option<&'static [u8]> {
+        match self {
+            DerivationScheme::V0 => None,
+            DerivationScheme::V1 => Some(b"v1"),
+        }
+    }
+
+    pub fn protocol_path(self, vault_id: u64) -> Vec<Vec<u8>> {
+        let mut path = vec![DOMAIN_LABEL.to_vec()];
+        path.extend(self.version_label().map(<[u8]>::to_vec));
+        path.push(PROTOCOL_ROLE_LABEL.to_vec());
+        path.push(vault_id.to_be_bytes().to_vec());
+        path
+    }
+
+    /// Layout of the protocol path, e.g. `usdb/v1/proto/<vault_id>`.
+    pub fn describe(self) -> String {
+        let mut segments = vec![String::from_utf8_lossy(DOMAIN_LABEL).into_owned()];
+        segments.extend(
+            self.version_label()
+                .map(|label| String::from_utf8_lossy(label).into_owned()),
+        );
+        segments.push(String::from_utf8_lossy(PROTOCOL_ROLE_LABEL).into_owned());
+        segments.push("<vault_id>".to_string());
+        segments.join("/")
+    }
+}
+
+/// Taproot internal key of every vault; key-path spends are signed with it.
+pub(crate) fn guardian_path() -> Vec<Vec<u8>> {
+    vec![DOMAIN_LABEL.to_vec(), GUARDIAN_ROLE_LABEL.to_vec()]
+}
+
+/// Guardian key after `generation` rotations, `usdb/guardian-gen/<generation>`;
+/// generation 0 is the original `guardian_path`.
+pub(crate) fn guardian_path_at(generation: u32) -> Vec<Vec<u8>> {
+    if generation == 0 {
+        return guardian_path();
+    }
+    vec![
+        DOMAIN_LABEL.to_vec(),
+        ROTATED_GUARDIAN_ROLE_LABEL.to_vec(),
+        generation.to_be_bytes().to_vec(),
+    ]
+}
+
+/// Key of the protocol change output, under either key type.
+pub(crate) fn protocol_change_path() -> Vec<Vec<u8>> {
+    vec![DOMAIN_LABEL.to_vec(), PROTOCOL_CHANGE_ROLE_LABEL.to_vec()]
+}
+
+/// Key of `cosign` signatures, under either key type.
+pub(crate) fn cosigning_path() -> Vec<Vec<u8>> {
+    vec![DOMAIN_LABEL.to_vec(), COSIGNING_ROLE_LABEL.to_vec()]
+}
+
+/// Throwaway key of one self-test run.
+pub(crate) fn self_test_path(nonce: u64) -> Vec<Vec<u8>> {
+    vec![
+        DOMAIN_LABEL.to_vec(),
+        SELF_TEST_ROLE_LABEL.to_vec(),
+        nonce.to_be_bytes().to_vec(),
+    ]
+}
+
+/// Domain separation findings: two roles or schemes yielding the same path,
+/// or one path being a prefix of another. Variable segments are fixed-width
+/// u64s, so checking boundary values covers every layout.
+pub(crate) fn audit() -> Vec<String> {
+    const SAMPLES: [u64; 3] = [0, 1, u64::MAX];
+    let mut paths = vec![
+        ("guardian".to_string(), guardian_path()),
+        ("change".to_string(), protocol_change_path()),
+        ("cosign".to_string(), cosigning_path()),
+    ];
+    for generation in [1, u32::MAX] {
+        paths.push((
+            format!("guardian({})", generation),
+            guardian_path_at(generation),
+        ));
+    }
+    for n in SAMPLES {
+        paths.push((format!("selftest({})", n), self_test_path(n)));
+        for scheme in DerivationScheme::ALL {
+            paths.push((
+                format!("{:?} protocol({})", scheme, n),
+                scheme.protocol_path(n),
+            ));
+        }
+    }
+    let mut findings = Vec::new();
+    for (i, (name_a, a)) in paths.iter().enumerate() {
+        for (name_b, b) in &paths[i + 1..] {
+            if a == b {
+                findings.push(format!("{} and {} derive the same path", name_a, name_b));
+            } else if b.starts_with(a) || a.starts_with(b) {
+                findings.push(format!("{} and {} share a path prefix", name_a, name_b));
+            }
+        }
+    }
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_path_is_unchanged() {
+        assert_eq!(
+            DerivationScheme::V0.protocol_path(7),
+            vec![
+                b"usdb".to_vec(),
+                b"proto".to_vec(),
+                7u64.to_be_bytes().to_vec()
+            ]
+        );
+        assert_eq!(DerivationScheme::V1.protocol_path(7)[1], b"v1".to_vec());
+        assert_eq!(DerivationScheme::V1.describe(), "usdb/v1/proto/<vault_id>");
+        assert_eq!(DerivationScheme::V0.describe(), "usdb/proto/<vault_id>");
+        assert_eq!(guardian_path_at(0), guardian_path());
+    }
+
+    #[test]
+    fn roles_and_schemes_are_separated() {
+        assert_eq!(audit(), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,182 @@
+// Threshold ECDSA over secp256k1 through the management canister, for the
+// outputs and integrations the Schnorr keys cannot serve: P2WPKH addresses and
+// legacy co-signing. The calls mirror the Schnorr ones in lib.rs: the same key
+// names, cycles accounting and error mapping.
+
+use candid::{CandidType, Principal};
+use ripemd::Ripemd160;
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+use sha2::{Digest, Sha256};
+
+use crate::{cycles_budget, record_cycles_usage, CyclesOperation, StablecoinError};
+
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+enum EcdsaCurve {
+    #[serde(rename = "secp256k1")]
+    Secp256k1,
+}
+
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+struct EcdsaKeyId {
+    curve: EcdsaCurve,
+    name: String,
+}
+
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+struct EcdsaPublicKeyArgument {
+    canister_id: Option<Principal>,
+    derivation_path: Vec<Vec<u8>>,
+    key_id: EcdsaKeyId,
+}
+
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+struct EcdsaPublicKeyResponse {
+    public_key: Vec<u8>,
+    chain_code: Vec<u8>,
+}
+
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+struct SignWithEcdsaArgument {
+    message_hash: ByteBuf,
+    derivation_path: Vec<Vec<u8>>,
+    key_id: EcdsaKeyId,
+}
+
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+struct SignWithEcdsaResponse {
+    signature: Vec<u8>,
+}
+
+fn key_id(key_name: &str) -> EcdsaKeyId {
+    EcdsaKeyId {
+        curve: EcdsaCurve::Secp256k1,
+        name: key_name.to_string(),
+    }
+}
+
+/// Compressed SEC1 public key and chain code for `derivation_path`.
+pub(crate) async fn public_key(
+    key_name: &str,
+    derivation_path: Vec<Vec<u8>>,
+) -> Result<([u8; 33], Vec<u8>), StablecoinError> {
+    let arg = EcdsaPublicKeyArgument {
+        canister_id: None,
+        derivation_path,
+        key_id: key_id(key_name),
+    };
+    let budget = cycles_budget(CyclesOperation::EcdsaPublicKey);
+    let result = ic_cdk::api::call::call_with_payment128(
+        Principal::management_canister(),
+        "ecdsa_public_key",
+        (arg,),
+        budget,
+    )
+    .await;
+    record_cycles_usage(CyclesOperation::EcdsaPublicKey, budget, &result);
+    let (response,): (EcdsaPublicKeyResponse,) = result.map_err(|(code, msg)| {
+        StablecoinError::SigningError(format!("ecdsa_public_key error {:?}: {}", code, msg))
+    })?;
+    let public_key = response
+        .public_key
+        .as_slice()
+        .try_into()
+        .ok()
+        .filter(|key: &[u8; 33]| key[0] == 0x02 || key[0] == 0x03)
+        .ok_or_else(|| StablecoinError::SigningError("invalid_ecdsa_pubkey".into()))?;
+    Ok((public_key, response.chain_code))
+}
+
+/// Compact `r || s` signature over `message_hash`.
+pub(crate) async fn sign(
+    key_name: &str,
+    derivation_path: Vec<Vec<u8>>,
+    message_hash: [u8; 32],
+) -> Result<[u8; 64], StablecoinError> {
+    let arg = SignWithEcdsaArgument {
+        message_hash: ByteBuf::from(message_hash.to_vec()),
+        derivation_path,
+        key_id: key_id(key_name),
+    };
+    let budget = cycles_budget(CyclesOperation::SignWithEcdsa);
+    let result = ic_cdk::api::call::call_with_payment128(
+        Principal::management_canister(),
+        "sign_with_ecdsa",
+        (arg,),
+        budget,
+    )
+    .await;
+    record_cycles_usage(CyclesOperation::SignWithEcdsa, budget, &result);
+    let (response,): (SignWithEcdsaResponse,) = result.map_err(|(code, msg)| {
+        StablecoinError::SigningError(format!("sign_with_ecdsa error {:?}: {}", code, msg))
+    })?;
+    response
+        .signature
+        .as_slice()
+        .try_into()
+        .map_err(|_| StablecoinError::SigningError("invalid_ecdsa_signature_length".into()))
+}
+
+/// DER encoding of a compact signature, as Bitcoin script signatures carry it
+/// (without the sighash byte). The management canister returns low-S values.
+pub(crate) fn der_signature(signature: &[u8; 64]) -> Vec<u8> {
+    fn integer(bytes: &[u8]) -> Vec<u8> {
+        let start = bytes
+            .iter()
+            .position(|b| *b != 0)
+            .unwrap_or(bytes.len() - 1);
+        let mut value = bytes[start..].to_vec();
+        if value[0] & 0x80 != 0 {
+            value.insert(0, 0);
+        }
+        let mut out = vec![0x02, value.len() as u8];
+        out.extend(value);
+        out
+    }
+    let r = integer(&signature[..32]);
+    let s = integer(&signature[32..]);
+    let mut out = vec![0x30, (r.len() + s.len()) as u8];
+    out.extend(r);
+    out.extend(s);
+    out
+}
+
+/// `OP_0 <hash160(public_key)>`, the P2WPKH output of a compressed key.
+pub(crate) fn p2wpkh_script(public_key: &[u8; 33]) -> Vec<u8> {
+    let hash = Ripemd160::digest(Sha256::digest(public_key));
+    let mut script = vec![0x00, 0x14];
+    script.extend_from_slice(&hash);
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn p2wpkh_commits_to_the_key_hash() {
+        // BIP-173 example key (the generator point)
+        let mut key = [0u8; 33];
+        key.copy_from_slice(
+            &crate::from_hex("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")
+                .unwrap(),
+        );
+        assert_eq!(
+            crate::to_hex(&p2wpkh_script(&key)),
+            "0014751e76e8199196d454941c45d1b3a323f1433bd6"
+        );
+    }
+
+    #[test]
+    fn der_signatures_are_minimal() {
+        let mut signature = [0u8; 64];
+        signature[1] = 0x7f;
+        signature[32] = 0x80;
+        let der = der_signature(&signature);
+        // r drops its leading zero; s needs one to stay positive
+        assert_eq!(&der[..4], &[0x30, 68, 0x02, 31]);
+        assert_eq!(der[4], 0x7f);
+        assert_eq!(&der[35..39], &[0x02, 33, 0x00, 0x80]);
+        assert_eq!(der.len(), 70);
+    }
+}
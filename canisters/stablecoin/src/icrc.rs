@@ -0,0 +1,121 @@
+// ICRC-1/ICRC-2 ledger calls for ckBTC collateral and the stablecoin ledger.
+// Only the fields this canister sends or reads are modelled; amounts stay
+// within u64 (sats of ckBTC, base units of the stablecoin).
+
+use candid::{CandidType, Nat, Principal};
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+
+use crate::StablecoinError;
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub(crate) struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<ByteBuf>,
+}
+
+#[derive(CandidType)]
+struct TransferArg {
+    from_subaccount: Option<ByteBuf>,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<ByteBuf>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType)]
+struct TransferFromArgs {
+    spender_subaccount: Option<ByteBuf>,
+    from: Account,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<ByteBuf>,
+    created_at_time: Option<u64>,
+}
+
+/// Union of the ICRC-1 transfer and ICRC-2 transfer_from errors.
+#[derive(Debug, CandidType, Deserialize)]
+enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(CandidType, Deserialize)]
+enum TransferResult {
+    Ok(Nat),
+    Err(TransferError),
+}
+
+fn ledger_error(method: &str, detail: impl std::fmt::Debug) -> StablecoinError {
+    StablecoinError::LedgerError(format!("{}: {:?}", method, detail))
+}
+
+fn block_index(method: &str, result: TransferResult) -> Result<u64, StablecoinError> {
+    match result {
+        TransferResult::Ok(block) => u64::try_from(block.0).map_err(|_| {
+            StablecoinError::LedgerError(format!("{}: block index out of range", method))
+        }),
+        TransferResult::Err(err) => Err(ledger_error(method, err)),
+    }
+}
+
+/// `icrc1_transfer` from one of this canister's subaccounts. Sent from the
+/// ledger's minting account, it mints.
+pub(crate) async fn transfer(
+    ledger: Principal,
+    from_subaccount: Option<[u8; 32]>,
+    to: Account,
+    amount: u64,
+) -> Result<u64, StablecoinError> {
+    let arg = TransferArg {
+        from_subaccount: from_subaccount.map(|s| ByteBuf::from(s.to_vec())),
+        to,
+        amount: Nat::from(amount),
+        fee: None,
+        memo: None,
+        created_at_time: None,
+    };
+    let (result,): (TransferResult,) = ic_cdk::call(ledger, "icrc1_transfer", (arg,))
+        .await
+        .map_err(|err| ledger_error("icrc1_transfer", err))?;
+    block_index("icrc1_transfer", result)
+}
+
+/// `icrc2_transfer_from` under the allowance `from` granted this canister.
+/// Sent to the ledger's minting account, it burns.
+pub(crate) async fn transfer_from(
+    ledger: Principal,
+    from: Account,
+    to: Account,
+    amount: u64,
+) -> Result<u64, StablecoinError> {
+    let args = TransferFromArgs {
+        spender_subaccount: None,
+        from,
+        to,
+        amount: Nat::from(amount),
+        fee: None,
+        memo: None,
+        created_at_time: None,
+    };
+    let (result,): (TransferResult,) = ic_cdk::call(ledger, "icrc2_transfer_from", (args,))
+        .await
+        .map_err(|err| ledger_error("icrc2_transfer_from", err))?;
+    block_index("icrc2_transfer_from", result)
+}
+
+pub(crate) async fn fee(ledger: Principal) -> Result<u64, StablecoinError> {
+    let (fee,): (Nat,) = ic_cdk::call(ledger, "icrc1_fee", ())
+        .await
+        .map_err(|err| ledger_error("icrc1_fee", err))?;
+    u64::try_from(fee.0).map_err(|_| ledger_error("icrc1_fee", "fee out of range"))
+}
@@ -0,0 +1,159 @@
+// Structured canister log. Subsystems log through `log!`, which still prints
+// to the replica log and also keeps the entry in a bounded ring that admins
+// read back with `get_logs`, so recent history is available without tailing
+// the replica. The ring is not persisted across upgrades.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+/// Entries kept before the oldest are dropped.
+const LOG_CAPACITY: usize = 2_000;
+/// Longer messages are truncated so a single entry cannot crowd the ring.
+const MAX_LOG_MESSAGE_LEN: usize = 1_024;
+
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, CandidType, Deserialize, Serialize,
+)]
+pub(crate) enum LogLevel {
+    Debug,
+    #[default]
+    Info,
+    Warn,
+    Error,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub(crate) struct LogEntry {
+    /// Increases by one per entry, across ring wrap-around.
+    pub seq: u64,
+    pub level: LogLevel,
+    pub timestamp: u64,
+    /// Subsystem or endpoint that wrote the entry.
+    pub module: String,
+    pub message: String,
+    /// Vault or redemption the entry belongs to, e.g. "vault:12".
+    pub correlation_id: Option<String>,
+}
+
+#[derive(Default)]
+struct LogRing {
+    entries: VecDeque<LogEntry>,
+    next_seq: u64,
+}
+
+impl LogRing {
+    fn push(&mut self, mut entry: LogEntry) {
+        self.next_seq += 1;
+        entry.seq = self.next_seq;
+        if self.entries.len() == LOG_CAPACITY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    fn query(&self, min_level: LogLevel, after_seq: u64, limit: usize) -> Vec<LogEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.seq > after_seq && entry.level >= min_level)
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+thread_local! {
+    static LOGS: RefCell<LogRing> = RefCell::new(LogRing::default());
+}
+
+/// Records `message` when `level` meets the configured minimum; use `log!`.
+pub(crate) fn record(
+    level: LogLevel,
+    module: &str,
+    correlation_id: Option<String>,
+    mut message: String,
+) {
+    if level < crate::log_level() {
+        return;
+    }
+    if message.len() > MAX_LOG_MESSAGE_LEN {
+        let mut end = MAX_LOG_MESSAGE_LEN;
+        while !message.is_char_boundary(end) {
+            end -= 1;
+        }
+        message.truncate(end);
+    }
+    ic_cdk::println!("[{}] {}", module, message);
+    let entry = LogEntry {
+        seq: 0,
+        level,
+        timestamp: ic_cdk::api::time(),
+        module: module.to_string(),
+        message,
+        correlation_id,
+    };
+    LOGS.with(|l| l.borrow_mut().push(entry));
+}
+
+/// Entries after `after_seq` at `min_level` or above, oldest first.
+pub(crate) fn entries(min_level: LogLevel, after_seq: u64, limit: usize) -> Vec<LogEntry> {
+    LOGS.with(|l| l.borrow().query(min_level, after_seq, limit))
+}
+
+/// `log!(Level, "module", "format", args..)`, optionally with
+/// `correlation = expr,` before the format string.
+macro_rules! log {
+    ($level:ident, $module:literal, correlation = $id:expr, $($arg:tt)+) => {
+        $crate::logging::record(
+            $crate::logging::LogLevel::$level,
+            $module,
+            Some($id.to_string()),
+            format!($($arg)+),
+        )
+    };
+    ($level:ident, $module:literal, $($arg:tt)+) => {
+        $crate::logging::record($crate::logging::LogLevel::$level, $module, None, format!($($arg)+))
+    };
+}
+
+pub(crate) use log;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(level: LogLevel) -> LogEntry {
+        LogEntry {
+            seq: 0,
+            level,
+            timestamp: 0,
+            module: "test".into(),
+            message: String::new(),
+            correlation_id: None,
+        }
+    }
+
+    #[test]
+    fn ring_drops_oldest_and_pages_by_seq() {
+        let mut ring = LogRing::default();
+        for i in 0..LOG_CAPACITY + 5 {
+            let level = if i % 2 == 0 {
+                LogLevel::Info
+            } else {
+                LogLevel::Warn
+            };
+            ring.push(entry(level));
+        }
+        assert_eq!(ring.entries.len(), LOG_CAPACITY);
+        assert_eq!(ring.entries.front().unwrap().seq, 6);
+        let page = ring.query(LogLevel::Warn, 0, 3);
+        assert_eq!(
+            page.iter().map(|e| e.seq).collect::<Vec<_>>(),
+            vec![6, 8, 10]
+        );
+        let next = ring.query(LogLevel::Debug, 2_004, 10);
+        assert_eq!(next.iter().map(|e| e.seq).collect::<Vec<_>>(), vec![2_005]);
+    }
+}
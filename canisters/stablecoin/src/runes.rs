@@ -0,0 +1,342 @@
+// Runestone decoding, enough to read the edicts of a withdrawal burn.
+// Etching and mint fields are skipped; anything the ord indexer would treat
+// as a cenotaph is reported as an error instead of a burn of every input rune.
+
+use std::fmt;
+
+use crate::script::{OP_PUSHDATA1, OP_PUSHDATA2, OP_PUSHDATA4, OP_RETURN};
+use crate::tx::{Reader, Transaction, TxOut};
+use crate::{invalid_input, StablecoinError};
+
+const OP_13: u8 = 0x5d;
+
+const TAG_BODY: u128 = 0;
+const TAG_POINTER: u128 = 22;
+/// Even tags the protocol defines; any other even tag makes a cenotaph.
+const KNOWN_EVEN_TAGS: [u128; 11] = [2, 4, 6, 8, 10, 12, 14, 16, 18, 20, 22];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct RuneId {
+    pub block: u64,
+    pub tx: u32,
+}
+
+impl RuneId {
+    /// Parses the `block:tx` form.
+    pub fn parse(s: &str) -> Result<Self, StablecoinError> {
+        let (block, tx) = s
+            .trim()
+            .split_once(':')
+            .ok_or_else(|| invalid_input("rune id must be block:tx"))?;
+        let id = RuneId {
+            block: block
+                .parse()
+                .map_err(|_| invalid_input("invalid rune id block"))?,
+            tx: tx
+                .parse()
+                .map_err(|_| invalid_input("invalid rune id tx"))?,
+        };
+        if id.block == 0 {
+            return Err(invalid_input("invalid rune id block"));
+        }
+        Ok(id)
+    }
+
+    /// Applies an edict's delta-encoded id to the previous one.
+    fn next(self, block: u128, tx: u128) -> Option<Self> {
+        let block = u64::try_from(block).ok()?;
+        let tx = u32::try_from(tx).ok()?;
+        if block == 0 {
+            Some(RuneId {
+                block: self.block,
+                tx: self.tx.checked_add(tx)?,
+            })
+        } else {
+            Some(RuneId {
+                block: self.block.checked_add(block)?,
+                tx,
+            })
+        }
+    }
+}
+
+impl fmt::Display for RuneId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.block, self.tx)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Edict {
+    pub id: RuneId,
+    /// 0 allocates every unallocated unit of the rune
+    pub amount: u128,
+    /// output index; equal to the output count splits across all outputs
+    pub output: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Runestone {
+    pub edicts: Vec<Edict>,
+    pub pointer: Option<u32>,
+}
+
+fn cenotaph(reason: &str) -> StablecoinError {
+    invalid_input(format!("cenotaph: {}", reason))
+}
+
+impl Runestone {
+    /// Decodes the runestone of `tx`: the first output whose script starts
+    /// with `OP_RETURN OP_13`. Returns None when there is none.
+    pub fn decipher(tx: &Transaction) -> Result<Option<Self>, StablecoinError> {
+        match tx
+            .outputs
+            .iter()
+            .find(|out| is_runestone(&out.script_pubkey))
+        {
+            Some(out) => Self::from_script(&out.script_pubkey, tx.outputs.len()).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Decodes a runestone output script on its own. Edict outputs and the
+    /// pointer are checked against `output_count`, so pass `usize::MAX` when
+    /// the transaction is unknown.
+    pub fn from_script(script: &[u8], output_count: usize) -> Result<Self, StablecoinError> {
+        let payload = match script {
+            [OP_RETURN, OP_13, rest @ ..] => rest,
+            _ => return Err(invalid_input("not_a_runestone")),
+        };
+        let integers = decode_integers(&payload_bytes(payload)?)?;
+
+        let mut runestone = Runestone::default();
+        let mut body: &[u128] = &[];
+        for (position, field) in integers.chunks(2).enumerate() {
+            let tag = field[0];
+            if tag == TAG_BODY {
+                body = &integers[position * 2 + 1..];
+                break;
+            }
+            let value = *field.get(1).ok_or_else(|| cenotaph("truncated_field"))?;
+            if tag % 2 == 0 && !KNOWN_EVEN_TAGS.contains(&tag) {
+                return Err(cenotaph("unrecognized_even_tag"));
+            }
+            if tag == TAG_POINTER {
+                runestone.pointer = Some(
+                    u32::try_from(value)
+                        .ok()
+                        .filter(|pointer| (*pointer as usize) < output_count)
+                        .ok_or_else(|| cenotaph("invalid_pointer"))?,
+                );
+            }
+        }
+
+        if !body.len().is_multiple_of(4) {
+            return Err(cenotaph("trailing_integers"));
+        }
+        let mut id = RuneId::default();
+        for chunk in body.chunks(4) {
+            id = id
+                .next(chunk[0], chunk[1])
+                .filter(|id| id.block != 0 || id.tx == 0)
+                .ok_or_else(|| cenotaph("edict_rune_id"))?;
+            let output = u32::try_from(chunk[3])
+                .ok()
+                .filter(|output| *output as usize <= output_count)
+                .ok_or_else(|| cenotaph("edict_output"))?;
+            runestone.edicts.push(Edict {
+                id,
+                amount: chunk[2],
+                output,
+            });
+        }
+        Ok(runestone)
+    }
+
+    /// Units of rune `id` that a transaction with these `outputs` burns when
+    /// its inputs hold `balance` of it. Edicts draw on what is still
+    /// unallocated, in order; units sent to an OP_RETURN output burn, and so
+    /// does the remainder when the pointer names one or no other output
+    /// exists. An edict can only move units the inputs actually hold.
+    pub fn burned(&self, outputs: &[TxOut], id: RuneId, balance: u128) -> u128 {
+        let burns = |vout: usize| {
+            outputs
+                .get(vout)
+                .is_none_or(|out| out.script_pubkey.first() == Some(&OP_RETURN))
+        };
+        let destinations: Vec<usize> = (0..outputs.len()).filter(|vout| !burns(*vout)).collect();
+        let mut unallocated = balance;
+        let mut burned = 0u128;
+        for edict in self.edicts.iter().filter(|edict| edict.id == id) {
+            if edict.output as usize == outputs.len() {
+                // Split across the non-OP_RETURN outputs, so nothing burns:
+                // each takes up to `amount` in turn, or an even share of
+                // everything when `amount` is 0.
+                if destinations.is_empty() {
+                    continue;
+                }
+                let count = destinations.len() as u128;
+                unallocated -= match edict.amount {
+                    0 => unallocated,
+                    amount => amount.saturating_mul(count).min(unallocated),
+                };
+                continue;
+            }
+            let amount = if edict.amount == 0 {
+                unallocated
+            } else {
+                edict.amount.min(unallocated)
+            };
+            unallocated -= amount;
+            if burns(edict.output as usize) {
+                burned += amount;
+            }
+        }
+        let remainder_vout = self
+            .pointer
+            .map(|pointer| pointer as usize)
+            .or_else(|| destinations.first().copied());
+        if remainder_vout.is_none_or(burns) {
+            burned += unallocated;
+        }
+        burned
+    }
+}
+
+/// Whether `script` is a runestone output: `OP_RETURN OP_13 ...`.
+pub(crate) fn is_runestone(script: &[u8]) -> bool {
+    matches!(script, [OP_RETURN, OP_13, ..])
+}
+
+/// Concatenates the data pushes in `script`, e.g. those following
+/// `OP_RETURN OP_13`. Any other opcode is an error.
+pub(crate) fn payload_bytes(script: &[u8]) -> Result<Vec<u8>, StablecoinError> {
+    let mut reader = Reader::new(script);
+    let mut payload = Vec::new();
+    while !reader.is_empty() {
+        let opcode = reader.read_u8()?;
+        let len = match opcode {
+            0x00..=0x4b => opcode as usize,
+            OP_PUSHDATA1 => reader.read_u8()? as usize,
+            OP_PUSHDATA2 => reader.read_u16()? as usize,
+            OP_PUSHDATA4 => reader.read_u32()? as usize,
+            _ => return Err(cenotaph("opcode")),
+        };
+        let data = reader
+            .read_bytes(len)
+            .map_err(|_| cenotaph("truncated_push"))?;
+        payload.extend_from_slice(data);
+    }
+    Ok(payload)
+}
+
+/// LEB128 integers, at most 128 bits each.
+fn decode_integers(payload: &[u8]) -> Result<Vec<u128>, StablecoinError> {
+    let mut integers = Vec::new();
+    let mut value: u128 = 0;
+    let mut shift = 0u32;
+    for (i, byte) in payload.iter().enumerate() {
+        let bits = u128::from(byte & 0x7f);
+        if shift > 126 || (shift == 126 && bits > 0b11) {
+            return Err(cenotaph("varint_overflow"));
+        }
+        value |= bits << shift;
+        if byte & 0x80 == 0 {
+            integers.push(value);
+            value = 0;
+            shift = 0;
+        } else if i + 1 == payload.len() {
+            return Err(cenotaph("truncated_varint"));
+        } else {
+            shift += 7;
+        }
+    }
+    Ok(integers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tx::{OutPoint, TxIn, TxOut};
+
+    fn tx_with_outputs(scripts: Vec<Vec<u8>>) -> Transaction {
+        Transaction {
+            version: 2,
+            inputs: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: [1u8; 32],
+                    vout: 0,
+                },
+                script_sig: vec![],
+                sequence: 0xffff_fffd,
+                witness: vec![],
+            }],
+            outputs: scripts
+                .into_iter()
+                .map(|script_pubkey| TxOut {
+                    value: if script_pubkey[0] == OP_RETURN {
+                        0
+                    } else {
+                        1_000
+                    },
+                    script_pubkey,
+                })
+                .collect(),
+            lock_time: 0,
+        }
+    }
+
+    #[test]
+    fn decodes_withdraw_burn_edict() {
+        // The backend's default withdrawal payload: body tag, then one edict
+        // sending 10 units of 95453:2 to output 0 (the OP_RETURN itself).
+        let tx = tx_with_outputs(vec![
+            vec![0x6a, 0x5d, 0x07, 0x00, 0xdd, 0xe9, 0x05, 0x02, 0x0a, 0x00],
+            vec![0x51, 0x20, 0x11, 0x22],
+        ]);
+        let runestone = Runestone::decipher(&tx).unwrap().unwrap();
+        assert_eq!(
+            runestone.edicts,
+            vec![Edict {
+                id: RuneId::parse("95453:2").unwrap(),
+                amount: 10,
+                output: 0,
+            }]
+        );
+
+        let no_runestone = tx_with_outputs(vec![vec![0x6a, 0x04, 0xde, 0xad, 0xbe, 0xef]]);
+        assert_eq!(Runestone::decipher(&no_runestone).unwrap(), None);
+
+        // edict output past the last output
+        let cenotaph = tx_with_outputs(vec![vec![
+            0x6a, 0x5d, 0x07, 0x00, 0xdd, 0xe9, 0x05, 0x02, 0x0a, 0x05,
+        ]]);
+        assert!(Runestone::decipher(&cenotaph).is_err());
+    }
+
+    #[test]
+    fn burns_only_units_the_inputs_hold() {
+        let id = RuneId::parse("95453:2").unwrap();
+        let tx = tx_with_outputs(vec![vec![0x6a, 0x5d], vec![0x51, 0x20, 0x11, 0x22]]);
+        let burn = |amount, output| Runestone {
+            edicts: vec![Edict { id, amount, output }],
+            pointer: None,
+        };
+
+        // An edict larger than the inputs hold moves only what they hold.
+        assert_eq!(burn(1_000, 0).burned(&tx.outputs, id, 10), 10);
+        assert_eq!(burn(1_000, 0).burned(&tx.outputs, id, 0), 0);
+        // Other runes and edicts to spendable outputs burn nothing; the
+        // remainder goes to the first spendable output.
+        let other = RuneId { block: 1, tx: 0 };
+        assert_eq!(burn(5, 0).burned(&tx.outputs, other, 10), 0);
+        assert_eq!(burn(5, 1).burned(&tx.outputs, id, 10), 0);
+        assert_eq!(burn(5, 2).burned(&tx.outputs, id, 10), 0);
+        // A pointer at the OP_RETURN burns whatever is left.
+        let pointed = Runestone {
+            pointer: Some(0),
+            ..burn(5, 1)
+        };
+        assert_eq!(pointed.burned(&tx.outputs, id, 10), 5);
+    }
+}
@@ -0,0 +1,294 @@
+// Script assembly for the tapscript leaves the canister commits to. Pushes
+// follow the minimal-encoding rules (BIP-62 / tapscript MINIMALDATA), so the
+// scripts built here hash to the same leaves Bitcoin Core derives from the
+// equivalent descriptor fragments.
+
+use crate::{invalid_input, StablecoinError};
+
+pub(crate) const OP_0: u8 = 0x00;
+pub(crate) const OP_PUSHDATA1: u8 = 0x4c;
+pub(crate) const OP_PUSHDATA2: u8 = 0x4d;
+pub(crate) const OP_PUSHDATA4: u8 = 0x4e;
+pub(crate) const OP_1NEGATE: u8 = 0x4f;
+pub(crate) const OP_1: u8 = 0x51;
+pub(crate) const OP_16: u8 = 0x60;
+pub(crate) const OP_RETURN: u8 = 0x6a;
+pub(crate) const OP_NUMEQUAL: u8 = 0x9c;
+pub(crate) const OP_CHECKSIG: u8 = 0xac;
+pub(crate) const OP_CHECKSIGVERIFY: u8 = 0xad;
+pub(crate) const OP_CHECKLOCKTIMEVERIFY: u8 = 0xb1;
+pub(crate) const OP_CHECKSEQUENCEVERIFY: u8 = 0xb2;
+pub(crate) const OP_CHECKSIGADD: u8 = 0xba;
+
+/// Largest data push a direct push opcode can express.
+const MAX_DIRECT_PUSH: usize = 0x4b;
+/// BIP-342 limit on keys in a single `multi_a` leaf.
+const MAX_MULTI_A_KEYS: usize = 999;
+/// `older(n)` / `after(n)` upper bound: the disable / sign bit must stay clear.
+const MAX_LOCK_VALUE: u32 = 0x7fff_ffff;
+
+/// Bitcoin Core's default dust relay fee, in sat/kvB.
+const DUST_RELAY_FEE_SAT_KVB: u64 = 3_000;
+
+/// Smallest standard value of an output paying `script_pubkey`: what it costs
+/// to create and later spend it at the dust relay fee, following Bitcoin
+/// Core's `GetDustThreshold` (546 for P2PKH, 294 for P2WPKH, 330 for P2TR).
+/// Unspendable OP_RETURN outputs have no limit.
+pub(crate) fn dust_limit(script_pubkey: &[u8]) -> u64 {
+    if script_pubkey.first() == Some(&OP_RETURN) {
+        return 0;
+    }
+    // value, script length and script
+    let output_size = 8 + 1 + script_pubkey.len() as u64;
+    // outpoint, sequence and script length, plus the signature data (discounted
+    // to a quarter for witness programs)
+    let input_size = match script_pubkey {
+        [OP_0 | OP_1..=OP_16, len, program @ ..]
+            if (2..=40).contains(len) && program.len() == *len as usize =>
+        {
+            32 + 4 + 1 + 107 / 4 + 4
+        }
+        _ => 32 + 4 + 1 + 107 + 4,
+    };
+    (output_size + input_size) * DUST_RELAY_FEE_SAT_KVB / 1_000
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct ScriptBuilder {
+    script: Vec<u8>,
+}
+
+impl ScriptBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push_opcode(mut self, opcode: u8) -> Self {
+        self.script.push(opcode);
+        self
+    }
+
+    /// Data push using the shortest opcode for its length.
+    pub fn push_slice(mut self, data: &[u8]) -> Self {
+        let len = data.len();
+        if len <= MAX_DIRECT_PUSH {
+            self.script.push(len as u8);
+        } else if len <= 0xff {
+            self.script.push(OP_PUSHDATA1);
+            self.script.push(len as u8);
+        } else if len <= 0xffff {
+            self.script.push(OP_PUSHDATA2);
+            self.script.extend_from_slice(&(len as u16).to_le_bytes());
+        } else {
+            self.script.push(OP_PUSHDATA4);
+            self.script.extend_from_slice(&(len as u32).to_le_bytes());
+        }
+        self.script.extend_from_slice(data);
+        self
+    }
+
+    /// Minimal push of a script number: `OP_0`, `OP_1NEGATE` and `OP_1`..`OP_16`
+    /// for the small values, otherwise the CScriptNum bytes as a data push.
+    pub fn push_int(self, n: i64) -> Self {
+        match n {
+            0 => self.push_opcode(OP_0),
+            -1 => self.push_opcode(OP_1NEGATE),
+            1..=16 => self.push_opcode(OP_1 + (n as u8 - 1)),
+            _ => self.push_slice(&encode_script_num(n)),
+        }
+    }
+
+    /// `<32-byte x-only key>`
+    pub fn push_x_only_key(self, key: &[u8; 32]) -> Self {
+        self.push_slice(key)
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.script
+    }
+}
+
+/// CScriptNum encoding: little-endian magnitude with the sign in the top bit
+/// of the last byte, empty for zero.
+pub(crate) fn encode_script_num(n: i64) -> Vec<u8> {
+    if n == 0 {
+        return Vec::new();
+    }
+    let negative = n < 0;
+    let mut magnitude = n.unsigned_abs();
+    let mut bytes = Vec::with_capacity(9);
+    while magnitude > 0 {
+        bytes.push((magnitude & 0xff) as u8);
+        magnitude >>= 8;
+    }
+    let last = bytes.len() - 1;
+    if bytes[last] & 0x80 != 0 {
+        bytes.push(if negative { 0x80 } else { 0x00 });
+    } else if negative {
+        bytes[last] |= 0x80;
+    }
+    bytes
+}
+
+/// `pk(K)`: `<K> OP_CHECKSIG`
+pub(crate) fn pk(key: &[u8; 32]) -> Vec<u8> {
+    ScriptBuilder::new()
+        .push_x_only_key(key)
+        .push_opcode(OP_CHECKSIG)
+        .into_bytes()
+}
+
+/// `multi_a(k,K1,...,Kn)`:
+/// `<K1> OP_CHECKSIG <K2> OP_CHECKSIGADD ... <Kn> OP_CHECKSIGADD <k> OP_NUMEQUAL`
+pub(crate) fn multi_a(threshold: usize, keys: &[[u8; 32]]) -> Result<Vec<u8>, StablecoinError> {
+    if keys.is_empty() || keys.len() > MAX_MULTI_A_KEYS {
+        return Err(invalid_input("unsupported_multi_a_keys"));
+    }
+    if threshold == 0 || threshold > keys.len() {
+        return Err(invalid_input("unsupported_multi_a_threshold"));
+    }
+    let mut builder = ScriptBuilder::new();
+    for (i, key) in keys.iter().enumerate() {
+        builder = builder.push_x_only_key(key).push_opcode(if i == 0 {
+            OP_CHECKSIG
+        } else {
+            OP_CHECKSIGADD
+        });
+    }
+    Ok(builder
+        .push_int(threshold as i64)
+        .push_opcode(OP_NUMEQUAL)
+        .into_bytes())
+}
+
+/// `and_v(v:pk(K),older(n))`: `<K> OP_CHECKSIGVERIFY <n> OP_CHECKSEQUENCEVERIFY`
+pub(crate) fn csv(key: &[u8; 32], blocks: u32) -> Result<Vec<u8>, StablecoinError> {
+    if blocks == 0 || blocks > MAX_LOCK_VALUE {
+        return Err(invalid_input("unsupported_older_value"));
+    }
+    Ok(ScriptBuilder::new()
+        .push_x_only_key(key)
+        .push_opcode(OP_CHECKSIGVERIFY)
+        .push_int(i64::from(blocks))
+        .push_opcode(OP_CHECKSEQUENCEVERIFY)
+        .into_bytes())
+}
+
+/// `and_v(v:pk(K),after(n))`: `<K> OP_CHECKSIGVERIFY <n> OP_CHECKLOCKTIMEVERIFY`
+pub(crate) fn cltv(key: &[u8; 32], lock_time: u32) -> Result<Vec<u8>, StablecoinError> {
+    if lock_time == 0 || lock_time > MAX_LOCK_VALUE {
+        return Err(invalid_input("unsupported_after_value"));
+    }
+    Ok(ScriptBuilder::new()
+        .push_x_only_key(key)
+        .push_opcode(OP_CHECKSIGVERIFY)
+        .push_int(i64::from(lock_time))
+        .push_opcode(OP_CHECKLOCKTIMEVERIFY)
+        .into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push_int(n: i64) -> Vec<u8> {
+        ScriptBuilder::new().push_int(n).into_bytes()
+    }
+
+    #[test]
+    fn minimal_number_pushes() {
+        assert_eq!(push_int(0), vec![OP_0]);
+        assert_eq!(push_int(-1), vec![OP_1NEGATE]);
+        assert_eq!(push_int(1), vec![0x51]);
+        assert_eq!(push_int(16), vec![0x60]);
+        assert_eq!(push_int(17), vec![0x01, 0x11]);
+        assert_eq!(push_int(127), vec![0x01, 0x7f]);
+        assert_eq!(push_int(128), vec![0x02, 0x80, 0x00]);
+        assert_eq!(push_int(144), vec![0x02, 0x90, 0x00]);
+        assert_eq!(push_int(-2), vec![0x01, 0x82]);
+        assert_eq!(push_int(-128), vec![0x02, 0x80, 0x80]);
+        assert_eq!(push_int(65_535), vec![0x03, 0xff, 0xff, 0x00]);
+        assert_eq!(push_int(0x7fff_ffff), vec![0x04, 0xff, 0xff, 0xff, 0x7f]);
+    }
+
+    #[test]
+    fn minimal_data_pushes() {
+        let push = |len: usize| {
+            ScriptBuilder::new()
+                .push_slice(&vec![7u8; len])
+                .into_bytes()
+        };
+        assert_eq!(push(75)[0], 75);
+        assert_eq!(push(76)[..2], [OP_PUSHDATA1, 76]);
+        assert_eq!(push(256)[..3], [OP_PUSHDATA2, 0x00, 0x01]);
+        assert_eq!(push(256).len(), 259);
+    }
+
+    #[test]
+    fn dust_limits_follow_the_script_type() {
+        let p2pkh = [&[0x76, 0xa9, 0x14][..], &[0u8; 20], &[0x88, 0xac]].concat();
+        let p2sh = [&[0xa9, 0x14][..], &[0u8; 20], &[0x87]].concat();
+        let p2wpkh = [&[OP_0, 0x14][..], &[0u8; 20]].concat();
+        let p2wsh = [&[OP_0, 0x20][..], &[0u8; 32]].concat();
+        let p2tr = [&[OP_1, 0x20][..], &[0u8; 32]].concat();
+        assert_eq!(dust_limit(&p2pkh), 546);
+        assert_eq!(dust_limit(&p2sh), 540);
+        assert_eq!(dust_limit(&p2wpkh), 294);
+        assert_eq!(dust_limit(&p2wsh), 330);
+        assert_eq!(dust_limit(&p2tr), 330);
+        assert_eq!(dust_limit(&[OP_RETURN, 0x01, 0x00]), 0);
+    }
+
+    #[test]
+    fn multi_a_template() {
+        let keys: Vec<[u8; 32]> = (1..=17u8).map(|i| [i; 32]).collect();
+        let script = multi_a(2, &keys[..2]).unwrap();
+        let mut expected = vec![0x20];
+        expected.extend_from_slice(&keys[0]);
+        expected.push(OP_CHECKSIG);
+        expected.push(0x20);
+        expected.extend_from_slice(&keys[1]);
+        expected.extend_from_slice(&[OP_CHECKSIGADD, 0x52, OP_NUMEQUAL]);
+        assert_eq!(script, expected);
+
+        // thresholds past OP_16 need a data push
+        let script = multi_a(17, &keys).unwrap();
+        assert_eq!(script.len(), 17 * 34 + 3);
+        assert_eq!(script[script.len() - 3..], [0x01, 0x11, OP_NUMEQUAL]);
+
+        assert!(multi_a(0, &keys).is_err());
+        assert!(multi_a(3, &keys[..2]).is_err());
+        assert!(multi_a(1, &[]).is_err());
+    }
+
+    #[test]
+    fn timelock_templates() {
+        let key = [3u8; 32];
+        let script = csv(&key, 144).unwrap();
+        assert_eq!(
+            script[33..],
+            [OP_CHECKSIGVERIFY, 0x02, 0x90, 0x00, OP_CHECKSEQUENCEVERIFY]
+        );
+        let script = csv(&key, 16).unwrap();
+        assert_eq!(
+            script[33..],
+            [OP_CHECKSIGVERIFY, 0x60, OP_CHECKSEQUENCEVERIFY]
+        );
+        assert!(csv(&key, 0).is_err());
+
+        let script = cltv(&key, 840_000).unwrap();
+        assert_eq!(
+            script[33..],
+            [
+                OP_CHECKSIGVERIFY,
+                0x03,
+                0x40,
+                0xd1,
+                0x0c,
+                OP_CHECKLOCKTIMEVERIFY
+            ]
+        );
+        assert!(cltv(&key, 0x8000_0000).is_err());
+        assert_eq!(pk(&key)[33], OP_CHECKSIG);
+    }
+}
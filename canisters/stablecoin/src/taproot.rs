@@ -0,0 +1,501 @@
+// BIP-341 signature hashing and script-path commitments. The canister derives
+// the digests it signs from the spending transaction itself instead of taking
+// them from the backend.
+
+use k256::elliptic_curve::point::{AffineCoordinates, DecompressPoint};
+use k256::elliptic_curve::subtle::Choice;
+use k256::elliptic_curve::PrimeField;
+use k256::{AffinePoint, ProjectivePoint, Scalar};
+
+use crate::script;
+use crate::tx::{sha256, write_var_bytes, Transaction, TxOut};
+use crate::{invalid_input, StablecoinError};
+
+pub(crate) const TAPSCRIPT_LEAF_VERSION: u8 = 0xc0;
+pub(crate) const SIGHASH_DEFAULT: u8 = 0x00;
+pub(crate) const SIGHASH_ALL: u8 = 0x01;
+
+const CONTROL_BLOCK_BASE_LEN: usize = 33;
+const CONTROL_BLOCK_NODE_LEN: usize = 32;
+const CONTROL_BLOCK_MAX_NODES: usize = 128;
+
+pub(crate) fn tagged_hash(tag: &str, data: &[u8]) -> [u8; 32] {
+    let tag_hash = sha256(tag.as_bytes());
+    let mut preimage = Vec::with_capacity(64 + data.len());
+    preimage.extend_from_slice(&tag_hash);
+    preimage.extend_from_slice(&tag_hash);
+    preimage.extend_from_slice(data);
+    sha256(&preimage)
+}
+
+pub(crate) fn tapleaf_hash(leaf_version: u8, script: &[u8]) -> [u8; 32] {
+    let mut data = vec![leaf_version];
+    write_var_bytes(&mut data, script);
+    tagged_hash("TapLeaf", &data)
+}
+
+fn tapbranch_hash(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+    let (left, right) = if a <= b { (a, b) } else { (b, a) };
+    let mut data = [0u8; 64];
+    data[..32].copy_from_slice(left);
+    data[32..].copy_from_slice(right);
+    tagged_hash("TapBranch", &data)
+}
+
+/// `internal_key + H_TapTweak(internal_key || merkle_root) * G`, returned as
+/// the x-only output key and the parity of its y coordinate. Key-path-only
+/// outputs have no merkle root.
+pub(crate) fn tweak_internal_key(
+    internal_key: &[u8; 32],
+    merkle_root: Option<&[u8; 32]>,
+) -> Result<([u8; 32], bool), StablecoinError> {
+    let point: Option<AffinePoint> =
+        AffinePoint::decompress(&(*internal_key).into(), Choice::from(0)).into();
+    let point = point.ok_or_else(|| invalid_input("invalid_internal_key"))?;
+    let mut data = internal_key.to_vec();
+    data.extend_from_slice(merkle_root.map_or(&[][..], |root| &root[..]));
+    let tweak: Option<Scalar> = Scalar::from_repr(tagged_hash("TapTweak", &data).into()).into();
+    let tweak = tweak.ok_or_else(|| invalid_input("invalid_taproot_tweak"))?;
+    let output = (ProjectivePoint::from(point) + ProjectivePoint::GENERATOR * tweak).to_affine();
+    Ok((output.x().into(), bool::from(output.y_is_odd())))
+}
+
+/// Checks that `leaf_script` is committed to by `output_key` through
+/// `control_block` and returns its leaf hash.
+pub(crate) fn verify_script_path(
+    output_key: &[u8; 32],
+    control_block: &[u8],
+    leaf_script: &[u8],
+) -> Result<[u8; 32], StablecoinError> {
+    let nodes_len = control_block
+        .len()
+        .checked_sub(CONTROL_BLOCK_BASE_LEN)
+        .ok_or_else(|| invalid_input("invalid_control_block_length"))?;
+    if nodes_len % CONTROL_BLOCK_NODE_LEN != 0
+        || nodes_len / CONTROL_BLOCK_NODE_LEN > CONTROL_BLOCK_MAX_NODES
+    {
+        return Err(invalid_input("invalid_control_block_length"));
+    }
+    let leaf_version = control_block[0] & 0xfe;
+    if leaf_version != TAPSCRIPT_LEAF_VERSION {
+        return Err(invalid_input("unsupported_leaf_version"));
+    }
+    let mut internal_key = [0u8; 32];
+    internal_key.copy_from_slice(&control_block[1..CONTROL_BLOCK_BASE_LEN]);
+    let leaf_hash = tapleaf_hash(leaf_version, leaf_script);
+    let mut node = leaf_hash;
+    for sibling in control_block[CONTROL_BLOCK_BASE_LEN..].chunks_exact(CONTROL_BLOCK_NODE_LEN) {
+        let mut sibling_arr = [0u8; 32];
+        sibling_arr.copy_from_slice(sibling);
+        node = tapbranch_hash(&node, &sibling_arr);
+    }
+    let (tweaked, odd) = tweak_internal_key(&internal_key, Some(&node))?;
+    if &tweaked != output_key || odd != (control_block[0] & 1 == 1) {
+        return Err(invalid_input("leaf_not_committed_to_output"));
+    }
+    Ok(leaf_hash)
+}
+
+/// Internal key and script tree of a `tr(...)` descriptor as produced for
+/// vaults: x-only or compressed hex keys, `pk`, `multi_a` and the
+/// `and_v(v:pk(K),older(n))` / `and_v(v:pk(K),after(n))` timelocked leaves.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct TaprootDescriptor {
+    pub internal_key: [u8; 32],
+    pub merkle_root: Option<[u8; 32]>,
+    /// Script leaves in descriptor order.
+    pub leaves: Vec<TapLeaf>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct TapLeaf {
+    pub script: Vec<u8>,
+    /// Sibling hashes from the leaf up to the root.
+    pub merkle_path: Vec<[u8; 32]>,
+}
+
+impl TaprootDescriptor {
+    pub fn parse(descriptor: &str) -> Result<Self, StablecoinError> {
+        let body = descriptor.split('#').next().unwrap_or_default().trim();
+        let inner = body
+            .strip_prefix("tr(")
+            .and_then(|rest| rest.strip_suffix(')'))
+            .ok_or_else(|| invalid_input("unsupported_descriptor"))?;
+        let (key, tree) = match split_top_level(inner) {
+            Some((key, tree)) => (key, Some(tree)),
+            None => (inner, None),
+        };
+        let (merkle_root, leaves) = match tree {
+            Some(tree) => {
+                let (root, leaves) = parse_tree(tree)?;
+                (Some(root), leaves)
+            }
+            None => (None, Vec::new()),
+        };
+        Ok(TaprootDescriptor {
+            internal_key: descriptor_key(key)?,
+            merkle_root,
+            leaves,
+        })
+    }
+
+    /// x-only output key the descriptor pays to.
+    pub fn output_key(&self) -> Result<[u8; 32], StablecoinError> {
+        Ok(tweak_internal_key(&self.internal_key, self.merkle_root.as_ref())?.0)
+    }
+
+    /// BIP-341 control block spending leaf `index`.
+    pub fn control_block(&self, index: usize) -> Result<Vec<u8>, StablecoinError> {
+        let leaf = self
+            .leaves
+            .get(index)
+            .ok_or_else(|| invalid_input("unknown_leaf"))?;
+        let (_, odd) = tweak_internal_key(&self.internal_key, self.merkle_root.as_ref())?;
+        let mut control_block = vec![TAPSCRIPT_LEAF_VERSION | odd as u8];
+        control_block.extend_from_slice(&self.internal_key);
+        for node in &leaf.merkle_path {
+            control_block.extend_from_slice(node);
+        }
+        Ok(control_block)
+    }
+}
+
+const DESCRIPTOR_INPUT_CHARSET: &[u8] =
+    b"0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const DESCRIPTOR_CHECKSUM_CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+fn descriptor_polymod(c: u64, value: u64) -> u64 {
+    const GENERATOR: [u64; 5] = [
+        0xf5_dee5_1989,
+        0xa9_fdca_3312,
+        0x1b_ab10_e32d,
+        0x37_06b1_677a,
+        0x64_4d62_6ffd,
+    ];
+    let top = c >> 35;
+    let mut c = ((c & 0x7_ffff_ffff) << 5) ^ value;
+    for (i, generator) in GENERATOR.iter().enumerate() {
+        if (top >> i) & 1 == 1 {
+            c ^= generator;
+        }
+    }
+    c
+}
+
+/// Appends the BIP-380 `#checksum` to a descriptor without one.
+pub(crate) fn with_descriptor_checksum(descriptor: &str) -> Result<String, StablecoinError> {
+    let mut c = 1u64;
+    let mut class = 0u64;
+    let mut class_count = 0;
+    for ch in descriptor.bytes() {
+        let position = DESCRIPTOR_INPUT_CHARSET
+            .iter()
+            .position(|&b| b == ch)
+            .ok_or_else(|| invalid_input("invalid_descriptor_character"))?
+            as u64;
+        c = descriptor_polymod(c, position & 31);
+        class = class * 3 + (position >> 5);
+        class_count += 1;
+        if class_count == 3 {
+            c = descriptor_polymod(c, class);
+            class = 0;
+            class_count = 0;
+        }
+    }
+    if class_count > 0 {
+        c = descriptor_polymod(c, class);
+    }
+    for _ in 0..8 {
+        c = descriptor_polymod(c, 0);
+    }
+    c ^= 1;
+    let checksum: String = (0..8)
+        .map(|j| DESCRIPTOR_CHECKSUM_CHARSET[((c >> (5 * (7 - j))) & 31) as usize] as char)
+        .collect();
+    Ok(format!("{}#{}", descriptor, checksum))
+}
+
+/// Splits `a,b` at the first comma outside parentheses and braces.
+fn split_top_level(s: &str) -> Option<(&str, &str)> {
+    let mut depth = 0i32;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' | '{' => depth += 1,
+            ')' | '}' => depth -= 1,
+            ',' if depth == 0 => return Some((&s[..i], &s[i + 1..])),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn descriptor_key(key: &str) -> Result<[u8; 32], StablecoinError> {
+    let bytes = crate::from_hex(key.trim())?;
+    match bytes.len() {
+        32 => crate::to_array_32(&bytes),
+        33 if bytes[0] == 0x02 || bytes[0] == 0x03 => crate::to_array_32(&bytes[1..]),
+        _ => Err(invalid_input("unsupported_descriptor_key")),
+    }
+}
+
+/// Returns the subtree hash and its leaves, with merkle paths up to this node.
+fn parse_tree(tree: &str) -> Result<([u8; 32], Vec<TapLeaf>), StablecoinError> {
+    let tree = tree.trim();
+    if let Some(branch) = tree.strip_prefix('{').and_then(|t| t.strip_suffix('}')) {
+        let (left, right) =
+            split_top_level(branch).ok_or_else(|| invalid_input("unsupported_descriptor_tree"))?;
+        let (left_hash, mut left_leaves) = parse_tree(left)?;
+        let (right_hash, right_leaves) = parse_tree(right)?;
+        for leaf in &mut left_leaves {
+            leaf.merkle_path.push(right_hash);
+        }
+        left_leaves.extend(right_leaves.into_iter().map(|mut leaf| {
+            leaf.merkle_path.push(left_hash);
+            leaf
+        }));
+        return Ok((tapbranch_hash(&left_hash, &right_hash), left_leaves));
+    }
+    let script = leaf_script(tree)?;
+    Ok((
+        tapleaf_hash(TAPSCRIPT_LEAF_VERSION, &script),
+        vec![TapLeaf {
+            script,
+            merkle_path: Vec::new(),
+        }],
+    ))
+}
+
+fn leaf_script(leaf: &str) -> Result<Vec<u8>, StablecoinError> {
+    if let Some(key) = leaf.strip_prefix("pk(").and_then(|l| l.strip_suffix(')')) {
+        return Ok(script::pk(&descriptor_key(key)?));
+    }
+    if let Some(args) = leaf
+        .strip_prefix("and_v(v:pk(")
+        .and_then(|l| l.strip_suffix(')'))
+    {
+        let (key, timelock) = args
+            .split_once("),")
+            .ok_or_else(|| invalid_input("unsupported_descriptor_leaf"))?;
+        let key = descriptor_key(key)?;
+        let value = |arg: &str, reason: &str| -> Result<u32, StablecoinError> {
+            arg.strip_suffix(')')
+                .and_then(|n| n.parse().ok())
+                .ok_or_else(|| invalid_input(reason))
+        };
+        if let Some(blocks) = timelock.strip_prefix("older(") {
+            return script::csv(&key, value(blocks, "unsupported_older_value")?);
+        }
+        if let Some(lock_time) = timelock.strip_prefix("after(") {
+            return script::cltv(&key, value(lock_time, "unsupported_after_value")?);
+        }
+        return Err(invalid_input("unsupported_descriptor_leaf"));
+    }
+    let args = leaf
+        .strip_prefix("multi_a(")
+        .and_then(|l| l.strip_suffix(')'))
+        .ok_or_else(|| invalid_input("unsupported_descriptor_leaf"))?;
+    let mut parts = args.split(',');
+    let threshold: usize = parts
+        .next()
+        .and_then(|k| k.trim().parse().ok())
+        .ok_or_else(|| invalid_input("unsupported_multi_a_threshold"))?;
+    let keys = parts.map(descriptor_key).collect::<Result<Vec<_>, _>>()?;
+    script::multi_a(threshold, &keys)
+}
+
+/// BIP-341 signature hash for `input_index`: a script-path spend of the leaf
+/// with `leaf_hash` (ext_flag 1, key_version 0, no OP_CODESEPARATOR), or a
+/// key-path spend when it is None. No annex. Only SIGHASH_DEFAULT and
+/// SIGHASH_ALL are supported, so every signature the canister produces commits
+/// to all inputs and outputs.
+pub(crate) fn taproot_sighash(
+    tx: &Transaction,
+    prevouts: &[TxOut],
+    input_index: usize,
+    leaf_hash: Option<&[u8; 32]>,
+    sighash_type: u8,
+) -> Result<[u8; 32], StablecoinError> {
+    if sighash_type != SIGHASH_DEFAULT && sighash_type != SIGHASH_ALL {
+        return Err(invalid_input("unsupported_sighash_type"));
+    }
+    if prevouts.len() != tx.inputs.len() || input_index >= tx.inputs.len() {
+        return Err(invalid_input("prevouts_do_not_match_inputs"));
+    }
+    let mut outpoints = Vec::with_capacity(tx.inputs.len() * 36);
+    let mut sequences = Vec::with_capacity(tx.inputs.len() * 4);
+    for input in &tx.inputs {
+        outpoints.extend_from_slice(&input.previous_output.txid);
+        outpoints.extend_from_slice(&input.previous_output.vout.to_le_bytes());
+        sequences.extend_from_slice(&input.sequence.to_le_bytes());
+    }
+    let mut amounts = Vec::with_capacity(prevouts.len() * 8);
+    let mut script_pubkeys = Vec::new();
+    for prevout in prevouts {
+        amounts.extend_from_slice(&prevout.value.to_le_bytes());
+        write_var_bytes(&mut script_pubkeys, &prevout.script_pubkey);
+    }
+    let mut outputs = Vec::new();
+    for output in &tx.outputs {
+        outputs.extend_from_slice(&output.value.to_le_bytes());
+        write_var_bytes(&mut outputs, &output.script_pubkey);
+    }
+
+    // epoch 0 || SigMsg(hash_type, ext_flag) || ext
+    let mut msg = Vec::with_capacity(1 + 175 + 37);
+    msg.push(0x00);
+    msg.push(sighash_type);
+    msg.extend_from_slice(&tx.version.to_le_bytes());
+    msg.extend_from_slice(&tx.lock_time.to_le_bytes());
+    msg.extend_from_slice(&sha256(&outpoints));
+    msg.extend_from_slice(&sha256(&amounts));
+    msg.extend_from_slice(&sha256(&script_pubkeys));
+    msg.extend_from_slice(&sha256(&sequences));
+    msg.extend_from_slice(&sha256(&outputs));
+    msg.push(if leaf_hash.is_some() { 2 } else { 0 }); // spend_type: ext_flag * 2, no annex
+    msg.extend_from_slice(&(input_index as u32).to_le_bytes());
+    if let Some(leaf_hash) = leaf_hash {
+        msg.extend_from_slice(leaf_hash);
+        msg.push(0x00); // key_version
+        msg.extend_from_slice(&u32::MAX.to_le_bytes()); // codesep_pos: none
+    }
+    Ok(tagged_hash("TapSighash", &msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tx::{p2tr_script_pubkey, OutPoint, TxIn};
+
+    // x coordinate of the secp256k1 generator
+    const INTERNAL_KEY: [u8; 32] = [
+        0x79, 0xbe, 0x66, 0x7e, 0xf9, 0xdc, 0xbb, 0xac, 0x55, 0xa0, 0x62, 0x95, 0xce, 0x87, 0x0b,
+        0x07, 0x02, 0x9b, 0xfc, 0xdb, 0x2d, 0xce, 0x28, 0xd9, 0x59, 0xf2, 0x81, 0x5b, 0x16, 0xf8,
+        0x17, 0x98,
+    ];
+
+    #[test]
+    fn matches_bip341_single_leaf_vector() {
+        let leaf_script =
+            crate::from_hex("20d85a959b0290bf19bb89ed43c916be835475d013da4b362117393e25a48229b8ac")
+                .unwrap();
+        let control_block =
+            crate::from_hex("c1187791b6f712a8ea41c8ecdd0ee77fab3e85263b37e1ec18a3651926b3a6cf27")
+                .unwrap();
+        let output_key = crate::to_array_32(
+            &crate::from_hex("147c9c57132f6e7ecddba9800bb0c4449251c92a1e60371ee77557b6620f3ea3")
+                .unwrap(),
+        )
+        .unwrap();
+        let leaf_hash = verify_script_path(&output_key, &control_block, &leaf_script).unwrap();
+        assert_eq!(
+            crate::to_hex(&leaf_hash),
+            "5b75adecf53548f3ec6ad7d78383bf84cc57b55a3127c72b9a2481752dd88b21"
+        );
+
+        let descriptor = TaprootDescriptor::parse(
+            "tr(187791b6f712a8ea41c8ecdd0ee77fab3e85263b37e1ec18a3651926b3a6cf27,\
+             pk(d85a959b0290bf19bb89ed43c916be835475d013da4b362117393e25a48229b8))",
+        )
+        .unwrap();
+        assert_eq!(descriptor.merkle_root, Some(leaf_hash));
+        assert_eq!(descriptor.output_key().unwrap(), output_key);
+
+        let key_only = TaprootDescriptor::parse(
+            "tr(d6889cb081036e0faefa3a35157ad71086b123b2b144b649798b494c300a961d)",
+        )
+        .unwrap();
+        assert_eq!(
+            crate::to_hex(&key_only.output_key().unwrap()),
+            "53a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343"
+        );
+    }
+
+    #[test]
+    fn script_path_commitment_and_sighash() {
+        let leaf_script = [0x20; 34];
+        let sibling = [5u8; 32];
+        let root = tapbranch_hash(
+            &tapleaf_hash(TAPSCRIPT_LEAF_VERSION, &leaf_script),
+            &sibling,
+        );
+        let (output_key, odd) = tweak_internal_key(&INTERNAL_KEY, Some(&root)).unwrap();
+        let mut control_block = vec![TAPSCRIPT_LEAF_VERSION | odd as u8];
+        control_block.extend_from_slice(&INTERNAL_KEY);
+        control_block.extend_from_slice(&sibling);
+
+        let leaf_hash = verify_script_path(&output_key, &control_block, &leaf_script).unwrap();
+        assert!(verify_script_path(&output_key, &control_block, &[0x51]).is_err());
+        control_block[0] ^= 1;
+        assert!(verify_script_path(&output_key, &control_block, &leaf_script).is_err());
+
+        let mut tx = Transaction {
+            version: 2,
+            inputs: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: [1u8; 32],
+                    vout: 0,
+                },
+                script_sig: vec![],
+                sequence: 0xffff_fffd,
+                witness: vec![],
+            }],
+            outputs: vec![TxOut {
+                value: 40_000,
+                script_pubkey: p2tr_script_pubkey(&[9u8; 32]),
+            }],
+            lock_time: 0,
+        };
+        let prevouts = [TxOut {
+            value: 50_000,
+            script_pubkey: p2tr_script_pubkey(&output_key),
+        }];
+        let default =
+            taproot_sighash(&tx, &prevouts, 0, Some(&leaf_hash), SIGHASH_DEFAULT).unwrap();
+        let all = taproot_sighash(&tx, &prevouts, 0, Some(&leaf_hash), SIGHASH_ALL).unwrap();
+        assert_ne!(default, all);
+        tx.outputs[0].value = 10_000;
+        let tampered =
+            taproot_sighash(&tx, &prevouts, 0, Some(&leaf_hash), SIGHASH_DEFAULT).unwrap();
+        assert_ne!(default, tampered);
+        assert!(taproot_sighash(&tx, &prevouts, 0, Some(&leaf_hash), 0x83).is_err());
+    }
+
+    #[test]
+    fn descriptor_checksum() {
+        assert_eq!(
+            with_descriptor_checksum("raw(deadbeef)").unwrap(),
+            "raw(deadbeef)#89f8spxm"
+        );
+    }
+
+    #[test]
+    fn recovery_leaf_script() {
+        let key = crate::to_hex(&INTERNAL_KEY);
+        let script = leaf_script(&format!("and_v(v:pk({}),older(144))", key)).unwrap();
+        let mut expected = vec![0x20];
+        expected.extend_from_slice(&INTERNAL_KEY);
+        expected.extend_from_slice(&[0xad, 0x02, 0x90, 0x00, 0xb2]);
+        assert_eq!(script, expected);
+
+        let three_leaves = format!(
+            "tr({k},{{{{pk({k}),pk({k})}},and_v(v:pk({k}),older(144))}})",
+            k = key
+        );
+        let descriptor = TaprootDescriptor::parse(&three_leaves).unwrap();
+        let output_key = descriptor.output_key().unwrap();
+        for (index, leaf) in descriptor.leaves.iter().enumerate() {
+            let control_block = descriptor.control_block(index).unwrap();
+            verify_script_path(&output_key, &control_block, &leaf.script).unwrap();
+        }
+        assert_eq!(descriptor.leaves[2].script, script);
+        let pk_leaf = leaf_script(&format!("pk({})", key)).unwrap();
+        let pk_hash = tapleaf_hash(TAPSCRIPT_LEAF_VERSION, &pk_leaf);
+        assert_eq!(
+            descriptor.merkle_root,
+            Some(tapbranch_hash(
+                &tapbranch_hash(&pk_hash, &pk_hash),
+                &tapleaf_hash(TAPSCRIPT_LEAF_VERSION, &script)
+            ))
+        );
+    }
+}
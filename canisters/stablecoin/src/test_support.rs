@@ -0,0 +1,214 @@
+// Deterministic hooks for PocketIC integration tests, compiled only with the
+// `test-support` feature. They stand in for the external dependencies the
+// canister cannot control under test (XRC price, Bitcoin canister UTXOs and
+// broadcasts, wall-clock time) so the mint, withdraw and liquidation paths run
+// against the real canister code. Never build a release wasm with it.
+
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+
+use candid::CandidType;
+use ic_cdk::api::call::CallResult;
+use ic_cdk::api::management_canister::bitcoin::{GetUtxosResponse, Utxo};
+use ic_cdk_macros::{query, update};
+use serde::Deserialize;
+
+use crate::{invalid_input, require_admin, BtcPrice, StablecoinError};
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+thread_local! {
+    static MOCK_PRICE: Cell<Option<BtcPrice>> = const { Cell::new(None) };
+    static TIME_OFFSET_NS: Cell<u64> = const { Cell::new(0) };
+    static UTXO_FIXTURES: RefCell<BTreeMap<String, GetUtxosResponse>> =
+        const { RefCell::new(BTreeMap::new()) };
+    static SENT_TRANSACTIONS: RefCell<Vec<Vec<u8>>> = const { RefCell::new(Vec::new()) };
+}
+
+pub(crate) fn mock_price() -> Option<BtcPrice> {
+    MOCK_PRICE.with(|p| p.get())
+}
+
+pub(crate) fn time_offset_ns() -> u64 {
+    TIME_OFFSET_NS.with(|t| t.get())
+}
+
+/// UTXO fixture for `address`; None falls through to the Bitcoin canister.
+pub(crate) fn utxo_fixture(address: &str) -> Option<CallResult<(GetUtxosResponse,)>> {
+    UTXO_FIXTURES.with(|f| f.borrow().get(address).cloned().map(|r| Ok((r,))))
+}
+
+/// Balance of `address` from its UTXO fixture, counting UTXOs with at least
+/// `min_confirmations` confirmations; None falls through to the Bitcoin canister.
+pub(crate) fn balance_fixture(
+    address: &str,
+    min_confirmations: Option<u32>,
+) -> Option<CallResult<(u64,)>> {
+    let min = min_confirmations.unwrap_or(0);
+    UTXO_FIXTURES.with(|f| {
+        f.borrow().get(address).map(|response| {
+            let confirmed = |utxo: &&Utxo| {
+                min == 0
+                    || (utxo.height > 0
+                        && response.tip_height.saturating_sub(utxo.height) + 1 >= min)
+            };
+            Ok((response
+                .utxos
+                .iter()
+                .filter(confirmed)
+                .map(|u| u.value)
+                .sum(),))
+        })
+    })
+}
+
+/// Records the transaction instead of sending it when any UTXO fixture is
+/// installed, so tests never depend on a Bitcoin canister being present.
+pub(crate) fn capture_transaction(transaction: &[u8]) -> Option<CallResult<()>> {
+    if UTXO_FIXTURES.with(|f| f.borrow().is_empty()) {
+        return None;
+    }
+    SENT_TRANSACTIONS.with(|s| s.borrow_mut().push(transaction.to_vec()));
+    Some(Ok(()))
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+struct TestClockReport {
+    now: u64,
+    offset_secs: u64,
+    /// periodic jobs run after advancing the clock
+    ran: Vec<String>,
+}
+
+/// Overrides the XRC price (None restores XRC) and reprices the vault
+/// indexes immediately.
+#[update]
+fn test_set_btc_price(price: Option<f64>) -> Result<(), StablecoinError> {
+    require_admin()?;
+    let price = mock_price_from_usd(price)?;
+    if let Some(price) = price {
+        crate::record_btc_usd_price(price);
+    }
+    MOCK_PRICE.with(|p| p.set(price));
+    Ok(())
+}
+
+fn mock_price_from_usd(price: Option<f64>) -> Result<Option<BtcPrice>, StablecoinError> {
+    price
+        .map(|usd| {
+            BtcPrice::from_usd(usd).ok_or_else(|| invalid_input("mock price must be positive"))
+        })
+        .transpose()
+}
+
+/// Serves `get_utxos` for `address` from the given set; an empty set
+/// simulates a spent vault.
+#[update]
+fn test_set_utxos(
+    address: String,
+    utxos: Vec<Utxo>,
+    tip_height: u32,
+) -> Result<(), StablecoinError> {
+    require_admin()?;
+    let response = GetUtxosResponse {
+        utxos,
+        tip_block_hash: vec![0u8; 32],
+        tip_height,
+        next_page: None,
+    };
+    UTXO_FIXTURES.with(|f| f.borrow_mut().insert(address, response));
+    Ok(())
+}
+
+#[update]
+fn test_clear_fixtures() -> Result<(), StablecoinError> {
+    require_admin()?;
+    UTXO_FIXTURES.with(|f| f.borrow_mut().clear());
+    SENT_TRANSACTIONS.with(|s| s.borrow_mut().clear());
+    MOCK_PRICE.with(|p| p.set(None));
+    Ok(())
+}
+
+/// Hex of every transaction captured instead of broadcast, oldest first.
+#[query]
+fn test_sent_transactions() -> Vec<String> {
+    SENT_TRANSACTIONS.with(|s| s.borrow().iter().map(|tx| crate::to_hex(tx)).collect())
+}
+
+/// Moves the canister clock forward by `secs` and runs the periodic jobs
+/// their timers would have fired in the meantime, so TTLs, spend windows and
+/// snapshot intervals can be crossed without waiting on real timers.
+#[update]
+async fn test_advance_time(secs: u64) -> Result<TestClockReport, StablecoinError> {
+    require_admin()?;
+    let offset = time_offset_ns().saturating_add(secs.saturating_mul(NANOS_PER_SEC));
+    TIME_OFFSET_NS.with(|t| t.set(offset));
+
+    let mut ran = Vec::new();
+    if secs >= crate::SIGNATURE_WATCHDOG_INTERVAL_SECS {
+        crate::check_issued_signatures().await;
+        ran.push("signature_watchdog".to_string());
+    }
+    let snapshot_interval =
+        crate::SETTINGS.with(|s| s.borrow().risk_snapshots.as_ref().map(|c| c.interval_secs));
+    if snapshot_interval.is_some_and(|interval| secs >= interval) {
+        crate::run_risk_snapshot().await;
+        ran.push("risk_snapshot".to_string());
+    }
+    Ok(TestClockReport {
+        now: crate::time(),
+        offset_secs: offset / NANOS_PER_SEC,
+        ran,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utxo(height: u32, value: u64) -> Utxo {
+        Utxo {
+            outpoint: ic_cdk::api::management_canister::bitcoin::Outpoint {
+                txid: vec![0; 32],
+                vout: 0,
+            },
+            value,
+            height,
+        }
+    }
+
+    #[test]
+    fn mock_prices_must_be_positive() {
+        assert!(mock_price_from_usd(None).unwrap().is_none());
+        assert!(mock_price_from_usd(Some(60_000.0)).unwrap().is_some());
+        for rejected in [0.0, -1.0, f64::NAN] {
+            assert!(matches!(
+                mock_price_from_usd(Some(rejected)),
+                Err(StablecoinError::InvalidInput(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn fixtures_fall_through_until_installed() {
+        assert!(utxo_fixture("bcrt1qvault").is_none());
+        assert!(capture_transaction(&[1, 2, 3]).is_none());
+
+        UTXO_FIXTURES.with(|f| {
+            f.borrow_mut().insert(
+                "bcrt1qvault".into(),
+                GetUtxosResponse {
+                    utxos: vec![utxo(100, 5_000), utxo(0, 700)],
+                    tip_block_hash: vec![0; 32],
+                    tip_height: 101,
+                    next_page: None,
+                },
+            )
+        });
+        assert_eq!(balance_fixture("bcrt1qvault", None), Some(Ok((5_700,))));
+        assert_eq!(balance_fixture("bcrt1qvault", Some(2)), Some(Ok((5_000,))));
+        assert_eq!(balance_fixture("bcrt1qvault", Some(3)), Some(Ok((0,))));
+        assert!(capture_transaction(&[1, 2, 3]).is_some());
+        SENT_TRANSACTIONS.with(|s| assert_eq!(s.borrow().len(), 1));
+    }
+}
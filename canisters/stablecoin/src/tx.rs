@@ -0,0 +1,550 @@
+// Minimal Bitcoin transaction encoding used for in-canister validation.
+// Only the consensus serialization is implemented here; script semantics are
+// left to the callers.
+
+use sha2::{Digest, Sha256};
+
+use crate::{invalid_input, to_hex, StablecoinError};
+
+/// Transactions larger than this are rejected by the decoder (standardness limit).
+const MAX_TX_BYTES: usize = 400_000;
+
+const PSBT_MAGIC: &[u8; 5] = b"psbt\xff";
+const PSBT_GLOBAL_UNSIGNED_TX: u8 = 0x00;
+const PSBT_IN_NON_WITNESS_UTXO: u8 = 0x00;
+const PSBT_IN_WITNESS_UTXO: u8 = 0x01;
+const PSBT_IN_SIGHASH_TYPE: u8 = 0x03;
+const PSBT_IN_FINAL_SCRIPTSIG: u8 = 0x07;
+const PSBT_IN_FINAL_SCRIPTWITNESS: u8 = 0x08;
+const PSBT_IN_TAP_KEY_SIG: u8 = 0x13;
+const PSBT_IN_TAP_SCRIPT_SIG: u8 = 0x14;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct OutPoint {
+    /// txid in internal (little-endian) byte order
+    pub txid: [u8; 32],
+    pub vout: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct TxIn {
+    pub previous_output: OutPoint,
+    pub script_sig: Vec<u8>,
+    pub sequence: u32,
+    pub witness: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct TxOut {
+    pub value: u64,
+    pub script_pubkey: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Transaction {
+    pub version: i32,
+    pub inputs: Vec<TxIn>,
+    pub outputs: Vec<TxOut>,
+    pub lock_time: u32,
+}
+
+impl Transaction {
+    pub fn has_witness(&self) -> bool {
+        self.inputs.iter().any(|input| !input.witness.is_empty())
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        self.encode(self.has_witness())
+    }
+
+    pub fn serialize_without_witness(&self) -> Vec<u8> {
+        self.encode(false)
+    }
+
+    fn encode(&self, with_witness: bool) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&self.version.to_le_bytes());
+        if with_witness {
+            out.extend_from_slice(&[0x00, 0x01]);
+        }
+        write_compact_size(&mut out, self.inputs.len() as u64);
+        for input in &self.inputs {
+            out.extend_from_slice(&input.previous_output.txid);
+            out.extend_from_slice(&input.previous_output.vout.to_le_bytes());
+            write_var_bytes(&mut out, &input.script_sig);
+            out.extend_from_slice(&input.sequence.to_le_bytes());
+        }
+        write_compact_size(&mut out, self.outputs.len() as u64);
+        for output in &self.outputs {
+            out.extend_from_slice(&output.value.to_le_bytes());
+            write_var_bytes(&mut out, &output.script_pubkey);
+        }
+        if with_witness {
+            for input in &self.inputs {
+                write_compact_size(&mut out, input.witness.len() as u64);
+                for item in &input.witness {
+                    write_var_bytes(&mut out, item);
+                }
+            }
+        }
+        out.extend_from_slice(&self.lock_time.to_le_bytes());
+        out
+    }
+
+    /// txid in internal byte order
+    pub fn txid(&self) -> [u8; 32] {
+        sha256d(&self.serialize_without_witness())
+    }
+
+    /// txid in the reversed, human-readable byte order used by explorers and RPC.
+    pub fn txid_hex(&self) -> String {
+        let mut txid = self.txid();
+        txid.reverse();
+        to_hex(&txid)
+    }
+
+    pub fn weight(&self) -> usize {
+        let base = self.serialize_without_witness().len();
+        let total = self.serialize().len();
+        base * 3 + total
+    }
+
+    pub fn vsize(&self) -> usize {
+        self.weight().div_ceil(4)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, StablecoinError> {
+        if bytes.len() > MAX_TX_BYTES {
+            return Err(invalid_input("transaction_too_large"));
+        }
+        let mut reader = Reader::new(bytes);
+        let version = reader.read_u32()? as i32;
+        let mut segwit = false;
+        if reader.peek() == Some(0x00) {
+            reader.read_u8()?;
+            if reader.read_u8()? != 0x01 {
+                return Err(invalid_input("invalid_segwit_flag"));
+            }
+            segwit = true;
+        }
+        let input_count = reader.read_count(41)?;
+        let mut inputs = Vec::with_capacity(input_count);
+        for _ in 0..input_count {
+            let txid = reader.read_array_32()?;
+            let vout = reader.read_u32()?;
+            let script_sig = reader.read_var_bytes()?;
+            let sequence = reader.read_u32()?;
+            inputs.push(TxIn {
+                previous_output: OutPoint { txid, vout },
+                script_sig,
+                sequence,
+                witness: Vec::new(),
+            });
+        }
+        let output_count = reader.read_count(9)?;
+        let mut outputs = Vec::with_capacity(output_count);
+        for _ in 0..output_count {
+            let value = reader.read_u64()?;
+            let script_pubkey = reader.read_var_bytes()?;
+            outputs.push(TxOut {
+                value,
+                script_pubkey,
+            });
+        }
+        if segwit {
+            for input in inputs.iter_mut() {
+                let items = reader.read_count(1)?;
+                for _ in 0..items {
+                    input.witness.push(reader.read_var_bytes()?);
+                }
+            }
+        }
+        let lock_time = reader.read_u32()?;
+        if !reader.is_empty() {
+            return Err(invalid_input("trailing_transaction_bytes"));
+        }
+        if inputs.is_empty() || outputs.is_empty() {
+            return Err(invalid_input("transaction_missing_inputs_or_outputs"));
+        }
+        Ok(Transaction {
+            version,
+            inputs,
+            outputs,
+            lock_time,
+        })
+    }
+}
+
+/// The parts of a BIP-174 (version 0) PSBT needed to check, sign or finalize
+/// a spend: the unsigned transaction and, per input, the previous output,
+/// requested sighash type and signatures when the PSBT carries them.
+#[derive(Clone, Debug)]
+pub(crate) struct Psbt {
+    pub unsigned_tx: Transaction,
+    pub prevouts: Vec<Option<TxOut>>,
+    pub sighash_types: Vec<Option<u32>>,
+    pub signatures: Vec<InputSignatures>,
+}
+
+/// Signing progress of one PSBT input.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct InputSignatures {
+    pub final_script_sig: Option<Vec<u8>>,
+    pub final_witness: Option<Vec<Vec<u8>>>,
+    /// BIP-371 key-path signature
+    pub tap_key_sig: Option<Vec<u8>>,
+    /// BIP-371 script-path signatures as (x-only key, leaf hash, signature)
+    pub tap_script_sigs: Vec<([u8; 32], [u8; 32], Vec<u8>)>,
+}
+
+impl InputSignatures {
+    /// Script-path signature of `key` for the leaf hashing to `leaf_hash`.
+    pub fn tap_script_sig(&self, key: &[u8; 32], leaf_hash: &[u8; 32]) -> Option<&[u8]> {
+        self.tap_script_sigs
+            .iter()
+            .find(|(k, leaf, _)| k == key && leaf == leaf_hash)
+            .map(|(_, _, signature)| signature.as_slice())
+    }
+}
+
+impl Psbt {
+    pub fn decode_base64(psbt: &str) -> Result<Self, StablecoinError> {
+        Self::decode(&base64_decode(psbt)?)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, StablecoinError> {
+        let mut reader = Reader::new(bytes);
+        if reader.read_bytes(PSBT_MAGIC.len())? != PSBT_MAGIC {
+            return Err(invalid_input("invalid_psbt_magic"));
+        }
+        let mut unsigned_tx = None;
+        while let Some((key, value)) = read_psbt_pair(&mut reader)? {
+            if key == [PSBT_GLOBAL_UNSIGNED_TX] {
+                unsigned_tx = Some(Transaction::decode(value)?);
+            }
+        }
+        let unsigned_tx = unsigned_tx.ok_or_else(|| invalid_input("psbt_missing_unsigned_tx"))?;
+        let mut prevouts = Vec::with_capacity(unsigned_tx.inputs.len());
+        let mut sighash_types = Vec::with_capacity(unsigned_tx.inputs.len());
+        let mut signatures = Vec::with_capacity(unsigned_tx.inputs.len());
+        for input in &unsigned_tx.inputs {
+            let mut prevout = None;
+            let mut sighash_type = None;
+            let mut sigs = InputSignatures::default();
+            while let Some((key, value)) = read_psbt_pair(&mut reader)? {
+                match key {
+                    [PSBT_IN_SIGHASH_TYPE] => {
+                        sighash_type = Some(Reader::new(value).read_u32()?);
+                    }
+                    [PSBT_IN_WITNESS_UTXO] => {
+                        let mut value_reader = Reader::new(value);
+                        prevout = Some(TxOut {
+                            value: value_reader.read_u64()?,
+                            script_pubkey: value_reader.read_var_bytes()?,
+                        });
+                    }
+                    [PSBT_IN_NON_WITNESS_UTXO] if prevout.is_none() => {
+                        let previous = Transaction::decode(value)?;
+                        if previous.txid() != input.previous_output.txid {
+                            return Err(invalid_input("psbt_prevout_txid_mismatch"));
+                        }
+                        prevout = previous
+                            .outputs
+                            .get(input.previous_output.vout as usize)
+                            .cloned();
+                    }
+                    [PSBT_IN_FINAL_SCRIPTSIG] => sigs.final_script_sig = Some(value.to_vec()),
+                    [PSBT_IN_FINAL_SCRIPTWITNESS] => {
+                        let mut value_reader = Reader::new(value);
+                        let items = value_reader.read_count(1)?;
+                        let mut witness = Vec::with_capacity(items);
+                        for _ in 0..items {
+                            witness.push(value_reader.read_var_bytes()?);
+                        }
+                        sigs.final_witness = Some(witness);
+                    }
+                    [PSBT_IN_TAP_KEY_SIG] => sigs.tap_key_sig = Some(value.to_vec()),
+                    [PSBT_IN_TAP_SCRIPT_SIG, rest @ ..] if rest.len() == 64 => {
+                        let mut key_reader = Reader::new(rest);
+                        sigs.tap_script_sigs.push((
+                            key_reader.read_array_32()?,
+                            key_reader.read_array_32()?,
+                            value.to_vec(),
+                        ));
+                    }
+                    _ => {}
+                }
+            }
+            prevouts.push(prevout);
+            sighash_types.push(sighash_type);
+            signatures.push(sigs);
+        }
+        Ok(Psbt {
+            unsigned_tx,
+            prevouts,
+            sighash_types,
+            signatures,
+        })
+    }
+
+    /// Sum of the spent outputs, or `None` if any input lacks its previous output.
+    pub fn input_value(&self) -> Option<u64> {
+        self.prevouts.iter().try_fold(0u64, |sum, prevout| {
+            sum.checked_add(prevout.as_ref()?.value)
+        })
+    }
+}
+
+/// Key and value of one PSBT map entry.
+type PsbtPair<'a> = (&'a [u8], &'a [u8]);
+
+/// Reads one key-value pair of a PSBT map, or `None` at the map separator.
+fn read_psbt_pair<'a>(reader: &mut Reader<'a>) -> Result<Option<PsbtPair<'a>>, StablecoinError> {
+    let key_len = reader.read_count(1)?;
+    if key_len == 0 {
+        return Ok(None);
+    }
+    let key = reader.read_bytes(key_len)?;
+    let value_len = reader.read_count(1)?;
+    let value = reader.read_bytes(value_len)?;
+    Ok(Some((key, value)))
+}
+
+/// Standard-alphabet base64 with optional padding; whitespace is ignored.
+pub(crate) fn base64_decode(input: &str) -> Result<Vec<u8>, StablecoinError> {
+    let mut out = Vec::with_capacity(input.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0u32;
+    let mut padding = 0usize;
+    for byte in input.bytes().filter(|b| !b.is_ascii_whitespace()) {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => {
+                padding += 1;
+                continue;
+            }
+            _ => return Err(invalid_input("invalid_base64")),
+        };
+        if padding > 0 {
+            return Err(invalid_input("invalid_base64"));
+        }
+        acc = (acc << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    if padding > 2 || bits >= 6 {
+        return Err(invalid_input("invalid_base64"));
+    }
+    Ok(out)
+}
+
+/// Standard-alphabet base64 with padding.
+pub(crate) fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |acc, (i, b)| acc | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+pub(crate) fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+pub(crate) fn sha256d(data: &[u8]) -> [u8; 32] {
+    sha256(&sha256(data))
+}
+
+/// `OP_1 <32-byte x-only key>`
+pub(crate) fn p2tr_script_pubkey(output_key: &[u8; 32]) -> Vec<u8> {
+    let mut script = Vec::with_capacity(34);
+    script.push(0x51);
+    script.push(0x20);
+    script.extend_from_slice(output_key);
+    script
+}
+
+pub(crate) fn write_compact_size(out: &mut Vec<u8>, n: u64) {
+    match n {
+        0..=0xfc => out.push(n as u8),
+        0xfd..=0xffff => {
+            out.push(0xfd);
+            out.extend_from_slice(&(n as u16).to_le_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(0xfe);
+            out.extend_from_slice(&(n as u32).to_le_bytes());
+        }
+        _ => {
+            out.push(0xff);
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+    }
+}
+
+pub(crate) fn write_var_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_compact_size(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.bytes.len().saturating_sub(self.pos)
+    }
+
+    pub fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], StablecoinError> {
+        if self.remaining() < len {
+            return Err(invalid_input("unexpected_end_of_data"));
+        }
+        let slice = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(slice)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, StablecoinError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, StablecoinError> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, StablecoinError> {
+        let mut arr = [0u8; 4];
+        arr.copy_from_slice(self.read_bytes(4)?);
+        Ok(u32::from_le_bytes(arr))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, StablecoinError> {
+        let mut arr = [0u8; 8];
+        arr.copy_from_slice(self.read_bytes(8)?);
+        Ok(u64::from_le_bytes(arr))
+    }
+
+    pub fn read_array_32(&mut self) -> Result<[u8; 32], StablecoinError> {
+        let mut arr = [0u8; 32];
+        arr.copy_from_slice(self.read_bytes(32)?);
+        Ok(arr)
+    }
+
+    pub fn read_compact_size(&mut self) -> Result<u64, StablecoinError> {
+        match self.read_u8()? {
+            0xfd => Ok(self.read_u16()? as u64),
+            0xfe => Ok(self.read_u32()? as u64),
+            0xff => self.read_u64(),
+            n => Ok(n as u64),
+        }
+    }
+
+    /// Reads an element count, rejecting values that could not possibly fit in
+    /// the remaining bytes given a minimum encoded size per element.
+    pub fn read_count(&mut self, min_item_size: usize) -> Result<usize, StablecoinError> {
+        let count = self.read_compact_size()?;
+        if count > (self.remaining() / min_item_size.max(1)) as u64 {
+            return Err(invalid_input("element_count_exceeds_data"));
+        }
+        Ok(count as usize)
+    }
+
+    pub fn read_var_bytes(&mut self) -> Result<Vec<u8>, StablecoinError> {
+        let len = self.read_count(1)?;
+        Ok(self.read_bytes(len)?.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segwit_round_trip() {
+        let tx = Transaction {
+            version: 2,
+            inputs: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: [7u8; 32],
+                    vout: 1,
+                },
+                script_sig: vec![],
+                sequence: 0xffff_fffd,
+                witness: vec![vec![0xaa; 64]],
+            }],
+            outputs: vec![TxOut {
+                value: 12_345,
+                script_pubkey: p2tr_script_pubkey(&[9u8; 32]),
+            }],
+            lock_time: 0,
+        };
+        let encoded = tx.serialize();
+        let decoded = Transaction::decode(&encoded).unwrap();
+        assert_eq!(decoded, tx);
+        assert_eq!(decoded.txid(), tx.txid());
+        assert!(tx.weight() > tx.serialize_without_witness().len() * 3);
+
+        let mut psbt = PSBT_MAGIC.to_vec();
+        let unsigned = tx.serialize_without_witness();
+        psbt.extend_from_slice(&[0x01, PSBT_GLOBAL_UNSIGNED_TX]);
+        write_var_bytes(&mut psbt, &unsigned);
+        psbt.push(0x00);
+        let mut witness_utxo = 20_000u64.to_le_bytes().to_vec();
+        write_var_bytes(&mut witness_utxo, &p2tr_script_pubkey(&[3u8; 32]));
+        psbt.extend_from_slice(&[0x01, PSBT_IN_WITNESS_UTXO]);
+        write_var_bytes(&mut psbt, &witness_utxo);
+        let mut key = vec![PSBT_IN_TAP_SCRIPT_SIG];
+        key.extend_from_slice(&[4u8; 32]);
+        key.extend_from_slice(&[5u8; 32]);
+        write_var_bytes(&mut psbt, &key);
+        write_var_bytes(&mut psbt, &[0xbb; 64]);
+        psbt.extend_from_slice(&[0x00, 0x00]);
+        let parsed = Psbt::decode(&psbt).unwrap();
+        assert_eq!(parsed.unsigned_tx.outputs, tx.outputs);
+        assert_eq!(parsed.input_value(), Some(20_000));
+        assert_eq!(
+            parsed.signatures[0].tap_script_sig(&[4u8; 32], &[5u8; 32]),
+            Some(&[0xbb; 64][..])
+        );
+    }
+
+    #[test]
+    fn decodes_base64() {
+        assert_eq!(base64_decode("aGVsbG8=").unwrap(), b"hello");
+        assert_eq!(base64_encode(b"hello"), "aGVsbG8=");
+        assert!(base64_decode("aGVsbG8=x").is_err());
+    }
+}
@@ -0,0 +1,264 @@
+// Stand-ins for the user's wallet and the backend's transaction building:
+// a key-path P2TR wallet that funds vaults, and the vault output itself with
+// the 2-of-2 redemption leaf the canister co-signs.
+
+use std::str::FromStr;
+
+use bitcoin::hashes::Hash;
+use bitcoin::key::{Keypair, TapTweak};
+use bitcoin::opcodes::all::{OP_CHECKSIG, OP_CHECKSIGADD, OP_NUMEQUAL};
+use bitcoin::script::Builder;
+use bitcoin::secp256k1::{schnorr, All, Message, Secp256k1, SecretKey, XOnlyPublicKey};
+use bitcoin::sighash::{Prevouts, SighashCache, TapSighashType};
+use bitcoin::taproot::{self, LeafVersion, TapLeafHash, TaprootBuilder, TaprootSpendInfo};
+use bitcoin::{
+    absolute, transaction, Address, Amount, Network, OutPoint, Psbt, ScriptBuf, Sequence,
+    Transaction, TxIn, TxOut, Txid, Witness,
+};
+
+pub const NETWORK: Network = Network::Testnet;
+
+/// Value of the single UTXO every wallet starts with.
+pub const WALLET_UTXO_SATS: u64 = 100_000;
+
+pub fn x_only_key(hex: &str) -> XOnlyPublicKey {
+    XOnlyPublicKey::from_str(hex).expect("x-only public key hex")
+}
+
+pub struct UserWallet {
+    secp: Secp256k1<All>,
+    keypair: Keypair,
+    pub address: Address,
+}
+
+impl UserWallet {
+    pub fn new(seed: u8) -> Self {
+        let secp = Secp256k1::new();
+        let secret = SecretKey::from_slice(&[seed; 32]).expect("valid secret key");
+        let keypair = Keypair::from_secret_key(&secp, &secret);
+        let address = Address::p2tr(&secp, keypair.x_only_public_key().0, None, NETWORK);
+        UserWallet {
+            secp,
+            keypair,
+            address,
+        }
+    }
+
+    pub fn x_only(&self) -> XOnlyPublicKey {
+        self.keypair.x_only_public_key().0
+    }
+
+    /// Deterministic outpoint standing in for a confirmed wallet UTXO.
+    fn utxo(&self) -> (OutPoint, TxOut) {
+        let txid = Txid::from_byte_array(self.keypair.secret_bytes());
+        let prevout = TxOut {
+            value: Amount::from_sat(WALLET_UTXO_SATS),
+            script_pubkey: self.address.script_pubkey(),
+        };
+        (OutPoint { txid, vout: 0 }, prevout)
+    }
+
+    /// Unsigned funding PSBT as the backend builds it: the vault output first,
+    /// change back to the wallet after `fee_sats`.
+    pub fn funding_psbt(&self, vault: &Vault, collateral_sats: u64, fee_sats: u64) -> Psbt {
+        let (outpoint, prevout) = self.utxo();
+        let change = WALLET_UTXO_SATS - collateral_sats - fee_sats;
+        let tx = unsigned_tx(
+            outpoint,
+            vec![
+                TxOut {
+                    value: Amount::from_sat(collateral_sats),
+                    script_pubkey: vault.address.script_pubkey(),
+                },
+                TxOut {
+                    value: Amount::from_sat(change),
+                    script_pubkey: self.address.script_pubkey(),
+                },
+            ],
+        );
+        let mut psbt = Psbt::from_unsigned_tx(tx).expect("unsigned transaction");
+        psbt.inputs[0].witness_utxo = Some(prevout);
+        psbt.inputs[0].tap_internal_key = Some(self.x_only());
+        psbt
+    }
+
+    /// Key-path signature over every input, as a wallet signs the funding PSBT.
+    pub fn sign_key_spend(&self, psbt: &mut Psbt) {
+        let prevouts = psbt_prevouts(psbt);
+        let tweaked = self.keypair.tap_tweak(&self.secp, None).to_keypair();
+        for index in 0..psbt.inputs.len() {
+            let sighash = SighashCache::new(&psbt.unsigned_tx)
+                .taproot_key_spend_signature_hash(
+                    index,
+                    &Prevouts::All(&prevouts),
+                    TapSighashType::Default,
+                )
+                .expect("key spend sighash");
+            let message = Message::from_digest(sighash.to_byte_array());
+            psbt.inputs[index].tap_key_sig = Some(taproot::Signature {
+                signature: self.secp.sign_schnorr_no_aux_rand(&message, &tweaked),
+                sighash_type: TapSighashType::Default,
+            });
+        }
+    }
+
+    /// Script-path signature with the untweaked key the vault leaf commits to.
+    pub fn sign_script_spend(&self, sighash: [u8; 32]) -> schnorr::Signature {
+        self.secp
+            .sign_schnorr_no_aux_rand(&Message::from_digest(sighash), &self.keypair)
+    }
+}
+
+/// Vault output: `multi_a(2, protocol, user)` under the guardian internal key.
+pub struct Vault {
+    secp: Secp256k1<All>,
+    protocol_key: XOnlyPublicKey,
+    leaf: ScriptBuf,
+    spend_info: TaprootSpendInfo,
+    pub address: Address,
+}
+
+impl Vault {
+    pub fn new(
+        protocol_key: XOnlyPublicKey,
+        user_key: XOnlyPublicKey,
+        internal_key: XOnlyPublicKey,
+    ) -> Self {
+        let secp = Secp256k1::new();
+        let leaf = Builder::new()
+            .push_x_only_key(&protocol_key)
+            .push_opcode(OP_CHECKSIG)
+            .push_x_only_key(&user_key)
+            .push_opcode(OP_CHECKSIGADD)
+            .push_int(2)
+            .push_opcode(OP_NUMEQUAL)
+            .into_script();
+        let spend_info = TaprootBuilder::new()
+            .add_leaf(0, leaf.clone())
+            .expect("single leaf")
+            .finalize(&secp, internal_key)
+            .expect("complete tree");
+        let address = Address::p2tr_tweaked(spend_info.output_key(), NETWORK);
+        Vault {
+            secp,
+            protocol_key,
+            leaf,
+            spend_info,
+            address,
+        }
+    }
+
+    pub fn leaf_script(&self) -> Vec<u8> {
+        self.leaf.to_bytes()
+    }
+
+    pub fn control_block(&self) -> Vec<u8> {
+        self.spend_info
+            .control_block(&(self.leaf.clone(), LeafVersion::TapScript))
+            .expect("leaf is in the tree")
+            .serialize()
+    }
+
+    /// Unsigned withdrawal paying the collateral, less `fee_sats`, to `payout`.
+    pub fn withdraw_psbt(
+        &self,
+        funding_txid: Txid,
+        collateral_sats: u64,
+        payout: &Address,
+        fee_sats: u64,
+    ) -> Psbt {
+        let tx = unsigned_tx(
+            OutPoint {
+                txid: funding_txid,
+                vout: 0,
+            },
+            vec![TxOut {
+                value: Amount::from_sat(collateral_sats - fee_sats),
+                script_pubkey: payout.script_pubkey(),
+            }],
+        );
+        let mut psbt = Psbt::from_unsigned_tx(tx).expect("unsigned transaction");
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: Amount::from_sat(collateral_sats),
+            script_pubkey: self.address.script_pubkey(),
+        });
+        psbt.inputs[0].tap_internal_key = Some(self.spend_info.internal_key());
+        psbt
+    }
+
+    /// BIP-341 digest of the redemption leaf spend, computed independently of
+    /// the canister.
+    pub fn script_spend_sighash(&self, psbt: &Psbt) -> [u8; 32] {
+        let prevouts = psbt_prevouts(psbt);
+        SighashCache::new(&psbt.unsigned_tx)
+            .taproot_script_spend_signature_hash(
+                0,
+                &Prevouts::All(&prevouts),
+                TapLeafHash::from_script(&self.leaf, LeafVersion::TapScript),
+                TapSighashType::Default,
+            )
+            .expect("script spend sighash")
+            .to_byte_array()
+    }
+
+    pub fn verify_protocol_signature(&self, sighash: [u8; 32], signature: &[u8]) -> bool {
+        schnorr::Signature::from_slice(signature).is_ok_and(|signature| {
+            self.secp
+                .verify_schnorr(
+                    &signature,
+                    &Message::from_digest(sighash),
+                    &self.protocol_key,
+                )
+                .is_ok()
+        })
+    }
+
+    /// Completes the leaf witness: `<user sig> <protocol sig> <leaf> <control>`;
+    /// the protocol signature is consumed first, so it sits on top.
+    pub fn finalize_script_spend(
+        &self,
+        psbt: &mut Psbt,
+        user_signature: schnorr::Signature,
+        protocol_signature: &[u8],
+    ) -> Transaction {
+        psbt.inputs[0].final_script_witness = Some(Witness::from_slice(&[
+            user_signature.as_ref().to_vec(),
+            protocol_signature.to_vec(),
+            self.leaf_script(),
+            self.control_block(),
+        ]));
+        psbt.clone().extract_tx().expect("finalized withdrawal")
+    }
+}
+
+/// Moves each `tap_key_sig` into the final witness and extracts the transaction.
+pub fn finalize_key_spend(psbt: &mut Psbt) -> Transaction {
+    for input in &mut psbt.inputs {
+        let signature = input.tap_key_sig.take().expect("input is signed");
+        input.final_script_witness = Some(Witness::p2tr_key_spend(&signature));
+    }
+    psbt.clone()
+        .extract_tx()
+        .expect("finalized funding transaction")
+}
+
+fn unsigned_tx(outpoint: OutPoint, outputs: Vec<TxOut>) -> Transaction {
+    Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: outpoint,
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            witness: Witness::new(),
+        }],
+        output: outputs,
+    }
+}
+
+fn psbt_prevouts(psbt: &Psbt) -> Vec<TxOut> {
+    psbt.inputs
+        .iter()
+        .map(|input| input.witness_utxo.clone().expect("witness utxo"))
+        .collect()
+}
@@ -17,8 +17,13 @@ pushd "$CRATE_DIR" >/dev/null
 # Respect custom cargo target dir if provided
 TARGET_DIR="${CARGO_TARGET_DIR:-$CRATE_DIR/target}"
 
-# Build to wasm32-unknown-unknown
-cargo build --target wasm32-unknown-unknown --release
+# Build to wasm32-unknown-unknown; CANISTER_FEATURES=test-support builds the
+# PocketIC test wasm
+FEATURE_ARGS=()
+if [[ -n "${CANISTER_FEATURES:-}" ]]; then
+  FEATURE_ARGS=(--features "$CANISTER_FEATURES")
+fi
+cargo build --target wasm32-unknown-unknown --release "${FEATURE_ARGS[@]}"
 
 WASM_PATH="$TARGET_DIR/wasm32-unknown-unknown/release/${CRATE_NAME}.wasm"
 